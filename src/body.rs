@@ -0,0 +1,361 @@
+use crate::Error;
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::stream::{Stream, StreamExt};
+use http::HeaderMap;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio_util::io::ReaderStream;
+
+type BoxStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+type FrameStream = Pin<Box<dyn Stream<Item = Result<Frame, Error>> + Send>>;
+
+/// A request body, either fully in memory or produced by an async stream.
+///
+/// In-memory bodies are sent with `Content-Length`. Streamed bodies, and any
+/// body carrying trailers, are sent with `Transfer-Encoding: chunked`.
+pub struct Body {
+    kind: Kind,
+    trailers: Option<HeaderMap>,
+}
+
+enum Kind {
+    Full(Bytes),
+    Stream(BoxStream),
+}
+
+impl Body {
+    pub fn empty() -> Self {
+        Body::from(Bytes::new())
+    }
+
+    pub fn from_stream<S>(stream: S) -> Self
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+    {
+        Body {
+            kind: Kind::Stream(Box::pin(stream)),
+            trailers: None,
+        }
+    }
+
+    pub fn from_reader<R>(reader: R) -> Self
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        Body::from_stream(ReaderStream::new(reader))
+    }
+
+    /// Trailer fields sent after the last chunk; forces chunked encoding.
+    pub fn with_trailers(mut self, trailers: HeaderMap) -> Self {
+        self.trailers = Some(trailers);
+        self
+    }
+
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        self.trailers.as_ref()
+    }
+
+    /// The body length when it is known up front.
+    pub fn len(&self) -> Option<u64> {
+        match &self.kind {
+            Kind::Full(bytes) => Some(bytes.len() as u64),
+            Kind::Stream(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0) && self.trailers.is_none()
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        matches!(self.kind, Kind::Stream(_)) || self.trailers.is_some()
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match &self.kind {
+            Kind::Full(bytes) => Some(bytes),
+            Kind::Stream(_) => None,
+        }
+    }
+
+    /// Clones an in-memory body; streamed bodies can only be sent once.
+    pub fn try_clone(&self) -> Option<Body> {
+        match &self.kind {
+            Kind::Full(bytes) => Some(Body {
+                kind: Kind::Full(bytes.clone()),
+                trailers: self.trailers.clone(),
+            }),
+            Kind::Stream(_) => None,
+        }
+    }
+
+    pub(crate) fn into_parts(self) -> (BoxStream, Option<HeaderMap>) {
+        let stream = match self.kind {
+            Kind::Full(bytes) => futures_util::stream::once(async move { Ok(bytes) }).boxed(),
+            Kind::Stream(stream) => stream,
+        };
+        (stream, self.trailers)
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Body::empty()
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            Kind::Full(bytes) => f.debug_tuple("Body").field(bytes).finish(),
+            Kind::Stream(_) => f.debug_tuple("Body").field(&"<stream>").finish(),
+        }
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Self {
+        Body {
+            kind: Kind::Full(bytes),
+            trailers: None,
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::from(Bytes::from(bytes))
+    }
+}
+
+impl From<&'static [u8]> for Body {
+    fn from(bytes: &'static [u8]) -> Self {
+        Body::from(Bytes::from_static(bytes))
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::from(Bytes::from(text))
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(text: &'static str) -> Self {
+        Body::from(Bytes::from_static(text.as_bytes()))
+    }
+}
+
+/// How much of a body that failed to parse as JSON ends up in the error.
+#[cfg(feature = "json")]
+const JSON_SNIPPET_LEN: usize = 256;
+
+#[cfg(feature = "json")]
+pub(crate) fn from_json<T: serde::de::DeserializeOwned>(status: Option<http::StatusCode>, bytes: &[u8]) -> Result<T, Error> {
+    serde_json::from_slice(bytes).map_err(|source| {
+        let mut snippet = String::from_utf8_lossy(&bytes[..bytes.len().min(JSON_SNIPPET_LEN)]).into_owned();
+        if bytes.len() > JSON_SNIPPET_LEN {
+            snippet.push_str("...");
+        }
+        Error::Json { status, snippet, source }
+    })
+}
+
+/// What a response body stream produces: data, then possibly trailers.
+pub(crate) enum Frame {
+    Data(Bytes),
+    Trailers(HeaderMap),
+}
+
+/// A response body, read from the connection as the caller consumes it.
+///
+/// The body can be pulled chunk by chunk, used as a `Stream` of `Bytes` or as
+/// an `AsyncRead`, or collected with `bytes()` and `text()`. The connection
+/// goes back to the client's pool once the body has been read to the end;
+/// dropping a body early closes the connection instead.
+pub struct ResponseBody {
+    inner: Incoming,
+    trailers: Option<HeaderMap>,
+    /// Data handed out by `poll_next` but not yet copied out by `poll_read`.
+    unread: Bytes,
+}
+
+enum Incoming {
+    Full(Option<Bytes>),
+    Frames(FrameStream),
+}
+
+impl ResponseBody {
+    pub fn empty() -> Self {
+        ResponseBody::from(Bytes::new())
+    }
+
+    pub(crate) fn from_frames<S>(frames: S) -> Self
+    where
+        S: Stream<Item = Result<Frame, Error>> + Send + 'static,
+    {
+        ResponseBody {
+            inner: Incoming::Frames(Box::pin(frames)),
+            trailers: None,
+            unread: Bytes::new(),
+        }
+    }
+
+    pub(crate) fn with_trailers(mut self, trailers: HeaderMap) -> Self {
+        self.trailers = Some(trailers);
+        self
+    }
+
+    /// The body if it has already been read into memory and not consumed yet.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match &self.inner {
+            Incoming::Full(Some(bytes)) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Trailer fields, available once the whole body has been read.
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        self.trailers.as_ref()
+    }
+
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        futures_util::future::poll_fn(|cx| self.poll_chunk(cx)).await.transpose()
+    }
+
+    pub async fn bytes(mut self) -> Result<Bytes, Error> {
+        if let (Incoming::Full(bytes), true) = (&mut self.inner, self.unread.is_empty()) {
+            return Ok(bytes.take().unwrap_or_default());
+        }
+        let mut collected = BytesMut::new();
+        while let Some(chunk) = self.chunk().await? {
+            collected.extend_from_slice(&chunk);
+        }
+        Ok(collected.freeze())
+    }
+
+    /// Collects the body as UTF-8, replacing invalid sequences.
+    pub async fn text(self) -> Result<String, Error> {
+        let bytes = self.bytes().await?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    #[cfg(feature = "json")]
+    pub async fn json<T: serde::de::DeserializeOwned>(self) -> Result<T, Error> {
+        let bytes = self.bytes().await?;
+        from_json(None, &bytes)
+    }
+
+    /// Puts a chunk taken with `chunk()` back in front of the rest.
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli", feature = "zstd"))]
+    pub(crate) fn push_front(&mut self, chunk: Bytes) {
+        debug_assert!(self.unread.is_empty());
+        self.unread = chunk;
+    }
+
+    fn poll_chunk(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Error>>> {
+        if !self.unread.is_empty() {
+            return Poll::Ready(Some(Ok(std::mem::take(&mut self.unread))));
+        }
+        match &mut self.inner {
+            Incoming::Full(bytes) => Poll::Ready(bytes.take().filter(|b| !b.is_empty()).map(Ok)),
+            Incoming::Frames(frames) => loop {
+                match frames.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(Ok(Frame::Data(data)))) if data.is_empty() => continue,
+                    Poll::Ready(Some(Ok(Frame::Data(data)))) => return Poll::Ready(Some(Ok(data))),
+                    Poll::Ready(Some(Ok(Frame::Trailers(trailers)))) => self.trailers = Some(trailers),
+                    Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                    Poll::Ready(None) => {
+                        self.inner = Incoming::Full(None);
+                        return Poll::Ready(None);
+                    }
+                    Poll::Pending => return Poll::Pending,
+                }
+            },
+        }
+    }
+}
+
+impl Default for ResponseBody {
+    fn default() -> Self {
+        ResponseBody::empty()
+    }
+}
+
+impl fmt::Debug for ResponseBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.inner {
+            Incoming::Full(Some(bytes)) => f.debug_tuple("ResponseBody").field(bytes).finish(),
+            Incoming::Full(None) => f.debug_tuple("ResponseBody").field(&"<consumed>").finish(),
+            Incoming::Frames(_) => f.debug_tuple("ResponseBody").field(&"<stream>").finish(),
+        }
+    }
+}
+
+impl From<Bytes> for ResponseBody {
+    fn from(bytes: Bytes) -> Self {
+        ResponseBody {
+            inner: Incoming::Full(Some(bytes)),
+            trailers: None,
+            unread: Bytes::new(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for ResponseBody {
+    fn from(bytes: Vec<u8>) -> Self {
+        ResponseBody::from(Bytes::from(bytes))
+    }
+}
+
+impl Stream for ResponseBody {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_chunk(cx)
+    }
+}
+
+impl AsyncRead for ResponseBody {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.unread.is_empty() {
+            match this.poll_chunk(cx) {
+                Poll::Ready(Some(Ok(chunk))) => this.unread = chunk,
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Err(io::Error::other(err))),
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let n = this.unread.len().min(buf.remaining());
+        buf.put_slice(&this.unread[..n]);
+        this.unread.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn collects_what_partial_reads_left() {
+        let mut body = ResponseBody::from(Bytes::from_static(b"hello world"));
+        let mut buf = [0; 6];
+        body.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello ");
+        assert_eq!(body.bytes().await.unwrap(), "world");
+
+        let frames = [b"abc", b"def"].map(|data| Ok(Frame::Data(Bytes::from_static(data))));
+        let mut body = ResponseBody::from_frames(stream::iter(frames));
+        let mut buf = [0; 2];
+        body.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ab");
+        assert_eq!(body.text().await.unwrap(), "cdef");
+    }
+}
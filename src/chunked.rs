@@ -0,0 +1,217 @@
+use crate::body::Body;
+use crate::http1::{parse_field_line, write_headers, OriginalHeaders};
+use crate::Error;
+use bytes::{Buf, BufMut, BytesMut};
+use futures_util::StreamExt;
+use http::HeaderMap;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+const MAX_LINE_LENGTH: usize = 8192;
+
+/// Incremental decoder for `Transfer-Encoding: chunked` bodies.
+///
+/// Bytes are fed in as they arrive from the connection; whatever can be
+/// decoded is appended to the output and the rest is left in the buffer
+/// until more data is available.
+#[derive(Debug)]
+pub struct ChunkedDecoder {
+    state: State,
+    trailers: HeaderMap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Size,
+    Data(u64),
+    DataEnd,
+    Trailers,
+    Done,
+}
+
+impl Default for ChunkedDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChunkedDecoder {
+    pub fn new() -> Self {
+        ChunkedDecoder {
+            state: State::Size,
+            trailers: HeaderMap::new(),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    pub fn into_trailers(self) -> HeaderMap {
+        self.trailers
+    }
+
+    pub fn decode(&mut self, buf: &mut BytesMut, out: &mut Vec<u8>) -> Result<(), Error> {
+        loop {
+            match self.state {
+                State::Size => {
+                    let Some(line) = take_line(buf)? else { return Ok(()) };
+                    let size = parse_chunk_size(&line)?;
+                    self.state = if size == 0 { State::Trailers } else { State::Data(size) };
+                }
+                State::Data(remaining) => {
+                    if buf.is_empty() {
+                        return Ok(());
+                    }
+                    let n = remaining.min(buf.len() as u64) as usize;
+                    out.extend_from_slice(&buf[..n]);
+                    buf.advance(n);
+                    let remaining = remaining - n as u64;
+                    self.state = if remaining == 0 { State::DataEnd } else { State::Data(remaining) };
+                }
+                State::DataEnd => {
+                    let Some(line) = take_line(buf)? else { return Ok(()) };
+                    if !line.is_empty() {
+                        return Err(Error::InvalidChunk("missing CRLF after chunk data"));
+                    }
+                    self.state = State::Size;
+                }
+                State::Trailers => {
+                    let Some(line) = take_line(buf)? else { return Ok(()) };
+                    if line.is_empty() {
+                        self.state = State::Done;
+                    } else {
+                        self.push_trailer(&line)?;
+                    }
+                }
+                State::Done => return Ok(()),
+            }
+        }
+    }
+
+    fn push_trailer(&mut self, line: &[u8]) -> Result<(), Error> {
+        let (name, value) = parse_field_line(line)?;
+        self.trailers.append(name, value);
+        Ok(())
+    }
+}
+
+/// Writes `body` using the chunked transfer-coding, ending with the last
+/// chunk and any trailers the body carries.
+pub async fn write_chunked<W>(writer: &mut W, body: Body) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let (mut stream, trailers) = body.into_parts();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        if chunk.is_empty() {
+            continue;
+        }
+        let mut frame = BytesMut::with_capacity(chunk.len() + 20);
+        frame.put_slice(format!("{:X}\r\n", chunk.len()).as_bytes());
+        frame.put_slice(&chunk);
+        frame.put_slice(b"\r\n");
+        writer.write_all(&frame).await?;
+    }
+
+    let mut last = b"0\r\n".to_vec();
+    if let Some(trailers) = &trailers {
+        write_headers(&mut last, trailers, &OriginalHeaders::new())?;
+    }
+    last.extend_from_slice(b"\r\n");
+    writer.write_all(&last).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Removes one line from the front of `buf`, without its CRLF or, as for
+/// response heads, bare LF.
+fn take_line(buf: &mut BytesMut) -> Result<Option<BytesMut>, Error> {
+    match buf.iter().position(|&b| b == b'\n') {
+        Some(end) => {
+            let mut line = buf.split_to(end);
+            buf.advance(1);
+            if line.ends_with(b"\r") {
+                line.truncate(end - 1);
+            }
+            Ok(Some(line))
+        }
+        None if buf.len() > MAX_LINE_LENGTH => Err(Error::InvalidChunk("chunk line too long")),
+        None => Ok(None),
+    }
+}
+
+/// Parses `chunk-size [ chunk-ext ]`; extensions are accepted and ignored.
+fn parse_chunk_size(line: &[u8]) -> Result<u64, Error> {
+    let size = match line.iter().position(|&b| b == b';') {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    let size = std::str::from_utf8(size).map_err(|_| Error::InvalidChunk("invalid chunk size"))?.trim_end_matches([' ', '\t']);
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(Error::InvalidChunk("invalid chunk size"));
+    }
+    u64::from_str_radix(size, 16).map_err(|_| Error::InvalidChunk("chunk size overflow"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::HeaderValue;
+
+    fn decode_all(parts: &[&[u8]]) -> (Vec<u8>, ChunkedDecoder) {
+        let mut decoder = ChunkedDecoder::new();
+        let mut buf = BytesMut::new();
+        let mut out = Vec::new();
+        for part in parts {
+            buf.extend_from_slice(part);
+            decoder.decode(&mut buf, &mut out).unwrap();
+        }
+        (out, decoder)
+    }
+
+    #[test]
+    fn decodes_chunks_split_across_reads() {
+        let (out, decoder) = decode_all(&[b"5\r\nhel", b"lo\r\n6\r", b"\n world\r\n0\r\n", b"\r\n"]);
+        assert_eq!(out, b"hello world");
+        assert!(decoder.is_done());
+    }
+
+    #[test]
+    fn ignores_extensions_and_collects_trailers() {
+        let (out, decoder) = decode_all(&[b"3;name=value\r\nabc\r\n0\r\nExpires: never\r\nX-Sum:  42\r\n\r\n"]);
+        assert_eq!(out, b"abc");
+        assert!(decoder.is_done());
+        let trailers = decoder.into_trailers();
+        assert_eq!(trailers["expires"], "never");
+        assert_eq!(trailers["x-sum"], "42");
+    }
+
+    #[test]
+    fn accepts_bare_line_feeds() {
+        let (out, decoder) = decode_all(&[b"5\nhello\n6;x=y\r\n world\n0\nX-Sum: 42\n", b"\n"]);
+        assert_eq!(out, b"hello world");
+        assert!(decoder.is_done());
+        assert_eq!(decoder.into_trailers()["x-sum"], "42");
+    }
+
+    #[tokio::test]
+    async fn encodes_stream_with_trailers() {
+        let chunks = vec![Ok(Bytes::from_static(b"hello")), Ok(Bytes::new()), Ok(Bytes::from_static(b" world!"))];
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-checksum", HeaderValue::from_static("abc"));
+        let body = Body::from_stream(futures_util::stream::iter(chunks)).with_trailers(trailers);
+
+        let mut out = Vec::new();
+        write_chunked(&mut out, body).await.unwrap();
+        assert_eq!(out, b"5\r\nhello\r\n7\r\n world!\r\n0\r\nx-checksum: abc\r\n\r\n");
+    }
+
+    #[test]
+    fn rejects_bad_chunk_size() {
+        let mut decoder = ChunkedDecoder::new();
+        let mut buf = BytesMut::from(&b"zz\r\n"[..]);
+        assert!(decoder.decode(&mut buf, &mut Vec::new()).is_err());
+    }
+}
@@ -1,24 +1,82 @@
-use crate::{Request, Response};
-use bytes::Bytes;
-use http::{HeaderMap, HeaderName, StatusCode};
-use rustls::{ClientConfig, RootCertStore};
-use std::error::Error;
-use std::str::FromStr;
+use crate::redirect::{is_redirect, next_method, strip_headers, Action, Attempt, Policy, MAX_REDIRECTS};
+use crate::{Body, Error, Request, RequestBuilder, Response, ResponseBody};
+use http::{HeaderMap, Method, Version};
+use rustls::ClientConfig;
+use std::io;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::sync::Mutex;
-use tokio_rustls::client::TlsStream;
+use std::time::Duration;
+use bytes::BytesMut;
+use futures_util::stream;
+use tokio::io::AsyncWriteExt;
+use tokio::time::Instant;
+use tokio::net::{lookup_host, TcpStream};
 use tokio_rustls::TlsConnector;
+use crate::chunked::write_chunked;
+use crate::cookie::CookieJar;
+use crate::body::Frame;
+use crate::decompress;
+use crate::http2::{self, Reason, SendRequest};
+use crate::http1::{encode_request_head, expects_continue, has_no_body, read_head, read_head_into, BodyReader, Framing, ResponseHead, TargetForm};
+use crate::pool::{Pool, PoolKey, Pooled};
+use crate::proxy::Proxy;
+use crate::tls::{self, Certificate, Identity, KeyLog, ServerCertVerifier, TlsInfo, TlsStats, TlsVersion};
+use crate::timeout::{timeout, Timed, TimeoutKind, Timeouts};
+use crate::transport::Transport;
 use url::Url;
-use http::header::CONTENT_LENGTH;
+use http::header::{ACCEPT_ENCODING, CONNECTION, COOKIE, LOCATION, PROXY_AUTHORIZATION};
 use http::HeaderValue;
 
+/// Used when `Timeouts::expect_continue` is unset.
+const DEFAULT_EXPECT_CONTINUE: Duration = Duration::from_secs(1);
 
 #[derive(Clone)]
 pub struct Client {
     tls_config: Arc<ClientConfig>,
-    tls_stream: Option<Arc<Mutex<TlsStream<TcpStream>>>>,
+    /// `tls_config` with early data enabled, sharing its session cache, so
+    /// that only connections meant for 0-RTT offer it.
+    early_data_config: Option<Arc<ClientConfig>>,
+    pool: Arc<Pool>,
+    max_response_body_size: Option<u64>,
+    timeouts: Timeouts,
+    redirect_policy: Policy,
+    http2_prior_knowledge: bool,
+    decompress: bool,
+    cookie_jar: Option<Arc<CookieJar>>,
+    proxies: Arc<Vec<Proxy>>,
+    tls_stats: Arc<tls::Counters>,
+}
+
+pub struct ClientBuilder {
+    pool_idle_timeout: Option<Duration>,
+    pool_max_per_host: usize,
+    max_response_body_size: Option<u64>,
+    timeouts: Timeouts,
+    redirect_policy: Policy,
+    http1_only: bool,
+    http2_prior_knowledge: bool,
+    decompress: bool,
+    cookie_jar: Option<Arc<CookieJar>>,
+    proxies: Vec<Proxy>,
+    /// A configuration error to report from `build`.
+    error: Option<Error>,
+    tls: tls::Options,
+}
+
+/// A connection ready to carry one request.
+enum Conn {
+    Http1(Pooled),
+    Http2(SendRequest),
+}
+
+impl Conn {
+    /// Whether a failure before any response may be down to the connection
+    /// going stale. HTTP/2 connections are shared, so they count as reused.
+    fn is_reused(&self) -> bool {
+        match self {
+            Conn::Http1(conn) => conn.is_reused(),
+            Conn::Http2(_) => true,
+        }
+    }
 }
 
 pub trait HeaderValueExt {
@@ -31,135 +89,1509 @@ impl HeaderValueExt for HeaderValue {
     }
 }
 
-impl Client {
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientBuilder {
     pub fn new() -> Self {
-        let root_cert_store = RootCertStore {
-            roots: webpki_roots::TLS_SERVER_ROOTS.into()
-        };
+        ClientBuilder {
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            pool_max_per_host: usize::MAX,
+            max_response_body_size: None,
+            timeouts: Timeouts::default(),
+            redirect_policy: Policy::default(),
+            http1_only: false,
+            http2_prior_knowledge: false,
+            decompress: true,
+            cookie_jar: None,
+            proxies: Vec::new(),
+            error: None,
+            tls: tls::Options::default(),
+        }
+    }
 
-        let mut tls_config = ClientConfig::builder()
-            .with_root_certificates(root_cert_store)
-            .with_no_client_auth();
+    /// How long a keep-alive connection may sit unused before it is closed.
+    /// `None` keeps idle connections forever, `Duration::ZERO` disables reuse.
+    pub fn pool_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.pool_idle_timeout = timeout;
+        self
+    }
 
-        tls_config.key_log = Arc::new(rustls::KeyLogFile::new());
+    /// Maximum number of connections open to a single origin at once; further
+    /// requests wait for one to become free.
+    pub fn pool_max_per_host(mut self, max: usize) -> Self {
+        self.pool_max_per_host = max.max(1);
+        self
+    }
 
-        Client {
-            tls_config: Arc::new(tls_config),
-            tls_stream: None,
+    /// Responses with a larger body fail with `Error::BodyTooLarge`. The limit
+    /// applies to the decoded body too when `Content-Encoding` is decoded.
+    pub fn max_response_body_size(mut self, limit: u64) -> Self {
+        self.max_response_body_size = Some(limit);
+        self
+    }
+
+    /// Default time limits for every request; see `Request::timeouts` for overrides.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// How `3xx` responses are handled; follows up to 10 redirects by default.
+    pub fn redirect(mut self, policy: Policy) -> Self {
+        self.redirect_policy = policy;
+        self
+    }
+
+    /// Never offer HTTP/2 during the TLS handshake.
+    pub fn http1_only(mut self) -> Self {
+        self.http1_only = true;
+        self
+    }
+
+    /// Speak HTTP/2 over plain `http://` connections without negotiating it
+    /// first (h2c with prior knowledge). Only use this for servers known to
+    /// support it.
+    pub fn http2_prior_knowledge(mut self) -> Self {
+        self.http2_prior_knowledge = true;
+        self
+    }
+
+    /// Whether to advertise the content codings enabled through cargo
+    /// features and decode response bodies that use them. On by default;
+    /// when off, or when a request sets its own `Accept-Encoding`, bodies
+    /// arrive exactly as the server sent them.
+    pub fn decompress(mut self, enabled: bool) -> Self {
+        self.decompress = enabled;
+        self
+    }
+
+    /// Stores cookies from responses in `jar` and sends them back on later
+    /// requests, redirects included. Pass a clone of the `Arc` to share one
+    /// jar between clients or to save it later. Requests that set their own
+    /// `Cookie` header are left alone. A jar from `CookieJar::new` has no
+    /// public suffix list, so cookies for parent domains are kept host-only;
+    /// see `CookieJar::with_public_suffix_list`.
+    pub fn cookie_jar(mut self, jar: Arc<CookieJar>) -> Self {
+        self.cookie_jar = Some(jar);
+        self
+    }
+
+    /// Sends matching requests through `proxy`. When several proxies are
+    /// added, the first one that applies to a request is used.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// Adds the proxies configured through `HTTP_PROXY`, `HTTPS_PROXY`,
+    /// `ALL_PROXY` and `NO_PROXY`; see `Proxy::from_env`. Without this, or
+    /// `proxy`, requests go straight to the server. A variable naming an
+    /// unsupported proxy, such as an `https://` one, makes `build` fail.
+    pub fn proxy_from_env(mut self) -> Self {
+        match Proxy::from_env() {
+            Ok(proxies) => self.proxies.extend(proxies),
+            Err(err) => self.error = self.error.or(Some(err)),
         }
+        self
+    }
+
+    /// Trusts `certificate` as a root, in addition to the built-in roots
+    /// unless those are turned off.
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> Self {
+        self.tls.roots.push(certificate);
+        self
     }
 
-    pub async fn connect(&mut self, url: Url) {
-        let host = url.host_str().ok_or("Invalid host").unwrap().to_string();
-        let port = match url.port() {
-            Some(port) => port,
-            None => if url.scheme() == "https" { 443 } else { 80 },
+    /// Whether to trust the Mozilla root store compiled into the crate. On by
+    /// default; turn it off to trust only roots added explicitly.
+    pub fn tls_built_in_roots(mut self, enabled: bool) -> Self {
+        self.tls.built_in_roots = enabled;
+        self
+    }
+
+    /// Whether to also trust the operating system's certificate store, read
+    /// when the client is built.
+    #[cfg(feature = "native-roots")]
+    pub fn tls_native_roots(mut self, enabled: bool) -> Self {
+        self.tls.native_roots = enabled;
+        self
+    }
+
+    /// The client certificate presented to servers that request one.
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.tls.identity = Some(identity);
+        self
+    }
+
+    /// The oldest TLS version to accept; TLS 1.2 by default.
+    pub fn min_tls_version(mut self, version: TlsVersion) -> Self {
+        self.tls.min_version = version;
+        self
+    }
+
+    /// Restricts the handshake to these cipher suites, in the crypto
+    /// provider's order of preference. Suites the provider lacks are ignored.
+    pub fn tls_cipher_suites(mut self, suites: &[rustls::CipherSuite]) -> Self {
+        self.tls.cipher_suites = Some(suites.to_vec());
+        self
+    }
+
+    /// Requires `host`'s certificate to carry a public key whose SPKI SHA-256
+    /// hash is `spki_sha256`, on top of the usual verification. Pinning a
+    /// host more than once allows any of the keys, e.g. a backup key.
+    pub fn pin_public_key(mut self, host: &str, spki_sha256: [u8; 32]) -> Self {
+        self.tls.pins.entry(host.to_ascii_lowercase()).or_default().push(spki_sha256);
+        self
+    }
+
+    /// Verifies server certificates with `verifier` instead of the root store.
+    /// Pins still apply after it accepts a certificate.
+    pub fn server_cert_verifier(mut self, verifier: Arc<dyn ServerCertVerifier>) -> Self {
+        self.tls.verification = tls::Verification::Custom(verifier);
+        self
+    }
+
+    /// Accepts any server certificate, expired, self-signed or issued for
+    /// another name. This leaves connections open to interception and is
+    /// meant only for local test setups.
+    pub fn danger_accept_invalid_certs(mut self) -> Self {
+        self.tls.verification = tls::Verification::DangerAcceptInvalidCerts;
+        self
+    }
+
+    /// How many TLS sessions to remember so that new connections can resume
+    /// them with a shorter handshake; 256 by default. The cache is shared by
+    /// every connection of the client and its clones. A server may issue a
+    /// few sessions at once, so leave room for several per server. `0`
+    /// always performs full handshakes.
+    pub fn tls_session_cache_size(mut self, sessions: usize) -> Self {
+        self.tls.session_cache_size = sessions;
+        self
+    }
+
+    /// Sends idempotent requests as TLS 1.3 early data (0-RTT) when resuming
+    /// a session with an HTTP/1.1 server that allows it, saving a round trip.
+    /// Early data can be replayed by an attacker, which is why other methods
+    /// never use it. A `425 Too Early` answer is retried after the handshake.
+    pub fn tls_early_data(mut self, enabled: bool) -> Self {
+        self.tls.early_data = enabled;
+        self
+    }
+
+    /// Hands the secrets of every TLS session to `sink`, so that captured
+    /// traffic can be decrypted. Off by default: anyone holding these secrets
+    /// can read the traffic, so only turn this on deliberately.
+    pub fn key_log(mut self, sink: Arc<dyn KeyLog>) -> Self {
+        self.tls.key_log = Some(sink);
+        self
+    }
+
+    /// Appends TLS secrets to the file named by `SSLKEYLOGFILE`, in the
+    /// format Wireshark reads. Logs nothing if the variable is unset.
+    pub fn key_log_file(self) -> Self {
+        self.key_log(Arc::new(crate::tls::KeyLogFile::new()))
+    }
+
+    /// Fails with `Error::TlsConfig` if the TLS settings do not fit together,
+    /// for example a client key that does not match its certificate.
+    pub fn build(self) -> Result<Client, Error> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        let early_data = self.tls.early_data;
+        let mut tls_config = self.tls.build()?;
+        tls_config.alpn_protocols = if self.http1_only {
+            vec![b"http/1.1".to_vec()]
+        } else {
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
         };
+        let early_data_config = early_data.then(|| {
+            let mut config = tls_config.clone();
+            config.enable_early_data = true;
+            Arc::new(config)
+        });
+
+        Ok(Client {
+            tls_config: Arc::new(tls_config),
+            early_data_config,
+            pool: Arc::new(Pool::new(self.pool_idle_timeout, self.pool_max_per_host)),
+            max_response_body_size: self.max_response_body_size,
+            timeouts: self.timeouts,
+            redirect_policy: self.redirect_policy,
+            http2_prior_knowledge: self.http2_prior_knowledge,
+            decompress: self.decompress,
+            cookie_jar: self.cookie_jar,
+            proxies: Arc::new(self.proxies),
+            tls_stats: Arc::default(),
+        })
+    }
+}
 
-        let addr = format!("{}:{}", host, port);
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        let stream = TcpStream::connect(addr).await.unwrap();
-        let connector = TlsConnector::from(self.tls_config.clone());
-        let domain = rustls_pki_types::ServerName::try_from(host).unwrap();
-        self.tls_stream = Some(Arc::new(Mutex::new(connector.connect(domain, stream).await.unwrap())));
+impl Client {
+    pub fn new() -> Self {
+        ClientBuilder::new().build().expect("the default TLS configuration is valid")
     }
 
-    pub async fn send_request(&mut self, request: Request, url: Url) -> Result<Response, Box<dyn Error + Send + Sync>> {
-        let mut request_str = format!("{} {} HTTP/1.1\r\n", request.method, url.path());
-        request_str += &format!("Host: {}\r\n", url.host_str().unwrap_or(""));
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
 
-        for (key, value) in &request.headers {
-            request_str += &format!("{}: {:?}\r\n", key.as_str(), value);
+    pub fn cookie_jar(&self) -> Option<&Arc<CookieJar>> {
+        self.cookie_jar.as_ref()
+    }
+
+    /// How many TLS handshakes this client and its clones have completed,
+    /// and how many of them resumed a session or used early data.
+    pub fn tls_stats(&self) -> TlsStats {
+        self.tls_stats.snapshot()
+    }
+
+    /// Opens a connection to the origin of `url` ahead of time and parks it
+    /// in the pool. `send_request` connects on demand, so this is optional.
+    pub async fn connect(&self, url: Url) -> Result<(), Error> {
+        let key = self.pool_key(&url)?;
+        let (permit, _) = self.pool.checkout(&key, false).await;
+        let transport = self.open(&key, false, &self.timeouts).await?;
+        Pooled::new(self.pool.clone(), key, transport, false, permit).release();
+        Ok(())
+    }
+
+    /// The pool key for `url`, including the proxy it is reached through.
+    fn pool_key(&self, url: &Url) -> Result<PoolKey, Error> {
+        let mut key = pool_key(url)?;
+        key.proxy = self.proxies.iter().find_map(|proxy| proxy.intercept(url)).cloned();
+        Ok(key)
+    }
+
+    /// With `early_data`, a resumable TLS session comes back before its
+    /// handshake is done, so that the request rides along as early data.
+    /// Such handshakes are counted once the response arrives.
+    async fn open(&self, key: &PoolKey, early_data: bool, timeouts: &Timeouts) -> Result<Transport, Error> {
+        let stream = timeout(timeouts.connect, TimeoutKind::Connect, Self::connect_via(key)).await?;
+        let transport = if key.scheme == "https" {
+            let config = match &self.early_data_config {
+                Some(config) if early_data => config.clone(),
+                _ => self.tls_config.clone(),
+            };
+            let connector = TlsConnector::from(config).early_data(early_data);
+            let domain = match key.host.parse::<std::net::IpAddr>() {
+                Ok(ip) => rustls_pki_types::ServerName::IpAddress(ip.into()),
+                Err(_) => rustls_pki_types::ServerName::try_from(key.host.clone())
+                    .map_err(|_| Error::InvalidUrl(format!("{} is not a valid server name", key.host)))?,
+            };
+            let handshake = async {
+                let connect = if early_data {
+                    // Early data must be in the protocol the session was made
+                    // for, which the caller knows to be HTTP/1.1.
+                    connector.with_alpn(vec![b"http/1.1".to_vec()]).connect(domain, stream)
+                } else {
+                    connector.connect(domain, stream)
+                };
+                connect.await.map_err(|err| handshake_error(&key.host, err))
+            };
+            let stream = timeout(timeouts.tls_handshake, TimeoutKind::TlsHandshake, handshake).await?;
+            Transport::Tls(Box::new(stream))
+        } else {
+            Transport::Plain(stream)
+        };
+        if let Some(info) = transport.tls_info().filter(|_| !transport.is_handshaking()) {
+            self.tls_stats.record(&info, false);
         }
+        Ok(transport)
+    }
 
-        if !request.body.is_empty() {
-            request_str += &format!("Content-Length: {}\r\n", request.body.len());
+    /// Connects to the origin, to a forward proxy, or through a tunnel.
+    async fn connect_via(key: &PoolKey) -> Result<TcpStream, Error> {
+        let Some(proxy) = &key.proxy else {
+            return Self::connect_tcp(&key.host, key.port).await;
+        };
+        let mut stream = Self::connect_tcp(&proxy.host, proxy.port).await?;
+        if !proxy.forwards(&key.scheme) {
+            proxy.tunnel(&mut stream, &key.host, key.port).await?;
         }
+        Ok(stream)
+    }
 
-        request_str += "\r\n";
+    /// Resolves `host` and tries each address in turn, keeping DNS failures
+    /// apart from failures to reach a resolved address.
+    async fn connect_tcp(host: &str, port: u16) -> Result<TcpStream, Error> {
+        let addrs = lookup_host((host, port)).await.map_err(|source| Error::Dns {
+            host: host.to_string(),
+            source,
+        })?;
 
-        self.clone().tls_stream.unwrap().lock().await.write_all(request_str.as_bytes()).await?;
-        if !request.body.is_empty() {
-            self.clone().tls_stream.unwrap().lock().await.write_all(&request.body).await?;
+        let mut last_err = None;
+        for addr in addrs {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = Some(err),
+            }
         }
 
-        let response = {
-            let mut stream = self.tls_stream.as_ref().unwrap().lock().await;
-            Self::read_response(&mut *stream).await?
+        Err(match last_err {
+            Some(source) => Error::Connect {
+                host: host.to_string(),
+                port,
+                source,
+            },
+            None => Error::Dns {
+                host: host.to_string(),
+                source: io::Error::new(io::ErrorKind::NotFound, "no addresses found"),
+            },
+        })
+    }
+
+    async fn checkout(&self, key: &PoolKey, reuse: bool, early_data: bool, timeouts: &Timeouts) -> Result<Pooled, Error> {
+        let (permit, idle) = self.pool.checkout(key, reuse).await;
+        let reused = idle.is_some();
+        let transport = match idle {
+            Some(transport) => transport,
+            None => self.open(key, early_data, timeouts).await?,
         };
+        Ok(Pooled::new(self.pool.clone(), key.clone(), transport, reused, permit))
+    }
 
-        Ok(response)
+    /// Finds a connection for `key`: the shared HTTP/2 connection if there is
+    /// one, otherwise a pooled or new connection, which becomes the shared one
+    /// if it turns out to speak HTTP/2. `early_data` is only honoured for
+    /// origins already known to speak HTTP/1.1.
+    async fn connection(&self, key: &PoolKey, reuse: bool, early_data: bool, timeouts: &Timeouts) -> Result<Conn, Error> {
+        if let Some(conn) = self.pool.http2(key) {
+            return Ok(Conn::Http2(conn));
+        }
+        let _dialing = self.pool.dial_lock(key).await;
+        if let Some(conn) = self.pool.http2(key) {
+            return Ok(Conn::Http2(conn));
+        }
+
+        let early_data = early_data && self.pool.is_http1(key);
+        let conn = self.checkout(key, reuse, early_data, timeouts).await?;
+        let http2 = match conn.transport() {
+            transport if transport.is_tls() => transport.alpn_protocol() == Some(b"h2"),
+            // A forward proxy speaks HTTP/1.1 to us, whatever the origin speaks.
+            _ => self.http2_prior_knowledge && !key.proxy.as_ref().is_some_and(|proxy| proxy.forwards(&key.scheme)),
+        };
+        if !http2 {
+            self.pool.set_http1(key);
+            return Ok(Conn::Http1(conn));
+        }
+        let tls_info = conn.transport().tls_info();
+        let conn = http2::handshake(conn, tls_info, self.pool.idle_timeout()).await?;
+        self.pool.put_http2(key.clone(), conn.clone());
+        Ok(Conn::Http2(conn))
+    }
+
+    pub fn get<U: AsRef<str>>(&self, url: U) -> RequestBuilder {
+        self.request(Method::GET, url)
+    }
+
+    pub fn post<U: AsRef<str>>(&self, url: U) -> RequestBuilder {
+        self.request(Method::POST, url)
+    }
+
+    pub fn put<U: AsRef<str>>(&self, url: U) -> RequestBuilder {
+        self.request(Method::PUT, url)
+    }
+
+    pub fn patch<U: AsRef<str>>(&self, url: U) -> RequestBuilder {
+        self.request(Method::PATCH, url)
+    }
+
+    pub fn delete<U: AsRef<str>>(&self, url: U) -> RequestBuilder {
+        self.request(Method::DELETE, url)
+    }
+
+    pub fn head<U: AsRef<str>>(&self, url: U) -> RequestBuilder {
+        self.request(Method::HEAD, url)
+    }
+
+    /// Starts a request; invalid input surfaces as an error from `send`.
+    pub fn request<U: AsRef<str>>(&self, method: Method, url: U) -> RequestBuilder {
+        RequestBuilder::new(self.clone(), method, url.as_ref())
     }
 
-    pub async fn read_response(stream: &mut TlsStream<TcpStream>) -> Result<Response, Box<dyn Error + Send + Sync>> {
-        let mut buffer = Vec::new();
-        let mut headers = [0; 8192]; // 8KB for headers
+    /// Sends `request` to `request.uri`, following redirects per the client's policy.
+    pub async fn send_request(&self, mut request: Request) -> Result<Response, Error> {
+        let accept_encoding = decompress::accept_encoding().filter(|_| self.decompress && !request.headers.contains_key(ACCEPT_ENCODING));
+        let method = request.method.clone();
+        let decode = accept_encoding.is_some();
+        if let Some(value) = accept_encoding {
+            request.headers.insert(ACCEPT_ENCODING, value);
+        }
 
+        let timeouts = request.timeouts.or(&self.timeouts);
+        let deadline = timeouts.total.map(|total| Instant::now() + total);
+        let mut response = timeout(timeouts.total, TimeoutKind::Total, self.follow_redirects(request, &timeouts, deadline)).await?;
+        if decode && !has_no_body(&method, response.status) {
+            let body = std::mem::take(&mut response.body);
+            response.body = decompress::decode(&mut response.headers, body, self.max_response_body_size);
+        }
+        Ok(response)
+    }
+
+    async fn follow_redirects(&self, mut request: Request, timeouts: &Timeouts, deadline: Option<Instant>) -> Result<Response, Error> {
+        let mut redirects = Vec::new();
         loop {
-            let n = stream.read(&mut headers).await?;
-            if n == 0 {
-                break;
+            let url = request.uri.clone();
+            let copy = match self.redirect_policy {
+                Policy::None => None,
+                _ => Some((request.clone_without_body(), request.body.try_clone())),
+            };
+            if let Some(jar) = self.cookie_jar.as_ref().filter(|_| !request.headers.contains_key(COOKIE)) {
+                if let Some(cookies) = jar.header(&url) {
+                    request.headers.insert(COOKIE, cookies);
+                }
             }
-            buffer.extend_from_slice(&headers[..n]);
-            if buffer.windows(4).any(|w| w == b"\r\n\r\n") {
-                break;
+            let mut response = self.send_with(request, timeouts, deadline).await?;
+            if let Some(jar) = &self.cookie_jar {
+                jar.store_response(&url, &response.headers);
+            }
+
+            let location = response
+                .headers
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| url.join(location).ok());
+            let (Some((mut next, body)), Some(location), true) = (copy, location, is_redirect(response.status)) else {
+                response.url = Some(url);
+                response.redirects = redirects;
+                return Ok(response);
+            };
+
+            if location == url || redirects.contains(&location) {
+                return Err(Error::RedirectLoop(location));
             }
+            redirects.push(url.clone());
+            let action = match &self.redirect_policy {
+                Policy::Limited(max) if redirects.len() > *max => return Err(Error::TooManyRedirects(*max)),
+                Policy::Custom(_) if redirects.len() > MAX_REDIRECTS => return Err(Error::TooManyRedirects(MAX_REDIRECTS)),
+                Policy::Custom(policy) => policy(&Attempt {
+                    status: response.status,
+                    next: &location,
+                    previous: &redirects,
+                }),
+                _ => Action::Follow,
+            };
+
+            let (method, keep_body) = next_method(response.status, &next.method);
+            let body = if keep_body { body } else { Some(Body::empty()) };
+            let Some(body) = body.filter(|_| action == Action::Follow) else {
+                // Stopped by the policy, or a streamed body that cannot be replayed.
+                redirects.pop();
+                response.url = Some(url);
+                response.redirects = redirects;
+                return Ok(response);
+            };
+
+            strip_headers(&mut next.headers, &url, &location, keep_body);
+            next.method = method;
+            next.uri = location;
+            next.body = body;
+            request = next;
         }
+    }
 
-        let (header_part, body_start) = {
-            let header_end = buffer.windows(4).position(|w| w == b"\r\n\r\n").ok_or("Invalid response")? + 4;
-            (&buffer[..header_end], &buffer[header_end..])
-        };
+    async fn send_with(&self, request: Request, timeouts: &Timeouts, deadline: Option<Instant>) -> Result<Response, Error> {
+        let url = request.uri.clone();
+        let key = self.pool_key(&url)?;
+        let early_data = self.early_data_config.is_some() && request.method.is_idempotent();
+        let conn = self.connection(&key, true, early_data, timeouts).await?;
+
+        // A server may close an idle keep-alive connection at any moment, so a
+        // reused connection that dies before answering is retried once on a
+        // fresh one, provided the body can be sent again and the method is
+        // idempotent: the server may have acted on it before the connection
+        // died. HTTP/2 streams the server refused, e.g. because it was going
+        // away, were never processed, so those are retried whatever the method.
+        let stale_retry = conn.is_reused() && request.method.is_idempotent();
+        let retry = if stale_retry || early_data || matches!(conn, Conn::Http2(_)) { request.try_clone() } else { None };
+        match (self.exchange(conn, request, &url, timeouts, deadline).await, retry) {
+            (Err(err), Some(retry)) if is_refused_stream(&err) || stale_retry && is_stale_connection(&err) => {
+                let conn = self.connection(&key, false, false, timeouts).await?;
+                self.exchange(conn, retry, &url, timeouts, deadline).await
+            }
+            // The server would not act on the early data (RFC 8470), so the
+            // request goes again on a connection that completes its handshake.
+            (Ok(response), Some(retry))
+                if response.status == http::StatusCode::TOO_EARLY && response.tls_info().is_some_and(TlsInfo::early_data) =>
+            {
+                let conn = self.connection(&key, true, false, timeouts).await?;
+                self.exchange(conn, retry, &url, timeouts, deadline).await
+            }
+            (result, _) => result,
+        }
+    }
 
-        let response_str = String::from_utf8_lossy(header_part);
-        let mut lines = response_str.lines();
+    async fn exchange(&self, conn: Conn, request: Request, url: &Url, timeouts: &Timeouts, deadline: Option<Instant>) -> Result<Response, Error> {
+        match conn {
+            Conn::Http1(conn) => self.exchange_http1(conn, request, url, timeouts, deadline).await,
+            Conn::Http2(conn) => {
+                let (head, body) = conn.send(request, url, self.max_response_body_size, timeouts, deadline).await?;
+                Ok(Self::response(head, body, conn.tls_info()))
+            }
+        }
+    }
 
-        let status_line = lines.next().ok_or("Missing status line")?;
-        let mut status_parts = status_line.splitn(3, ' ');
-        let _http_version = status_parts.next().ok_or("Missing HTTP version")?;
-        let status_code = status_parts.next().ok_or("Missing status code")?.parse::<u16>()?;
-        let status = StatusCode::from_u16(status_code)?;
-        let _reason_phrase = status_parts.next().unwrap_or("");
+    async fn exchange_http1(&self, conn: Pooled, mut request: Request, url: &Url, timeouts: &Timeouts, deadline: Option<Instant>) -> Result<Response, Error> {
+        let forward = conn.key().proxy.as_ref().filter(|proxy| proxy.forwards(url.scheme()));
+        if let Some(authorization) = forward.and_then(|proxy| proxy.authorization()) {
+            if !request.headers.contains_key(PROXY_AUTHORIZATION) {
+                request.headers.insert(PROXY_AUTHORIZATION, authorization);
+            }
+        }
+        let early_data = !conn.is_reused() && conn.transport().is_handshaking();
+        let form = TargetForm::select(&request.method, url, forward.is_some());
+        let head = encode_request_head(&request, url, form)?;
+
+        let mut keep_alive = !has_connection_close(&request.headers);
+        let mut stream = Timed::new(conn, timeouts, deadline);
+        stream.write_all(&head).await?;
 
-        let mut headers_map = HeaderMap::new();
-        for line in lines {
-            if line.is_empty() {
-                break;
+        let mut buf = BytesMut::new();
+        let mut informational = Vec::new();
+        let mut early = None;
+        if expects_continue(&request.headers) && !request.body.is_empty() {
+            stream.flush().await?;
+            let wait = timeouts.expect_continue.unwrap_or(DEFAULT_EXPECT_CONTINUE);
+            // Servers that ignore the expectation never answer, so the body
+            // goes out anyway once the wait is over.
+            if let Ok(head) = tokio::time::timeout(wait, read_head_into(&mut stream, &mut buf, &mut informational, true)).await {
+                early = head?;
+            }
+        }
+        let head = match early {
+            Some(head) => {
+                // The server answered without seeing the body, so the rest of
+                // the message can no longer be framed on this connection.
+                keep_alive = false;
+                head
             }
-            if let Some((key, value)) = line.split_once(": ") {
-                let header_name = HeaderName::from_str(key)?;
-                let header_value = HeaderValue::from_str(value)?;
-                headers_map.insert(header_name, header_value);
+            None => {
+                if request.body.is_chunked() {
+                    write_chunked(&mut stream, request.body).await?;
+                } else if let Some(body) = request.body.as_bytes().filter(|body| !body.is_empty()) {
+                    stream.write_all(body).await?;
+                }
+                // Early data sits in the TLS session until flushed.
+                stream.flush().await?;
+                read_head_into(&mut stream, &mut buf, &mut informational, false)
+                    .await?
+                    .expect("only stops at 100 Continue when asked to")
             }
+        };
+        let tls_info = stream.get_ref().transport().tls_info().map(Arc::new);
+        if let Some(info) = tls_info.as_deref().filter(|_| early_data) {
+            self.tls_stats.record(info, true);
         }
+        let framing = Framing::of(&head, &request.method)?;
+        if let (Some(limit), Framing::Length(length)) = (self.max_response_body_size, framing) {
+            if length > limit {
+                return Err(Error::BodyTooLarge { limit });
+            }
+        }
+        let keep_alive = keep_alive && is_keep_alive(&head);
 
-        let content_length = if let Some(value) = headers_map.get(CONTENT_LENGTH) {
-            Some(value.to_string().parse::<usize>()?)
+        let body = if framing == Framing::Length(0) {
+            if keep_alive && buf.is_empty() {
+                stream.into_inner().release();
+            }
+            ResponseBody::empty()
         } else {
-            None
+            let reader = BodyReader::new(stream, framing, buf, self.max_response_body_size);
+            ResponseBody::from_frames(stream::try_unfold(Some(reader), move |reader| async move {
+                let Some(mut reader) = reader else { return Ok(None) };
+                match reader.next_chunk().await? {
+                    Some(chunk) => Ok(Some((Frame::Data(chunk), Some(reader)))),
+                    None => {
+                        let (stream, trailers, reusable) = reader.into_parts();
+                        if keep_alive && reusable {
+                            stream.into_inner().release();
+                        }
+                        Ok(Some((Frame::Trailers(trailers), None)))
+                    }
+                }
+            }))
         };
 
-        let mut body = body_start.to_vec();
+        Ok(Self::response(head, body, tls_info))
+    }
+
+    /// Reads one complete response from `stream`, buffering the whole body
+    /// and decoding it if its `Content-Encoding` is supported. The request is
+    /// taken to be a GET: a response to HEAD would never finish reading.
+    pub async fn read_response(stream: &mut Transport) -> Result<Response, Error> {
+        let tls_info = stream.tls_info().map(Arc::new);
+        let (mut head, buf) = read_head(stream).await?;
+        let mut reader = BodyReader::new(stream, Framing::of(&head, &Method::GET)?, buf, None);
+        let mut raw = Vec::new();
+        while let Some(chunk) = reader.next_chunk().await? {
+            raw.extend_from_slice(&chunk);
+        }
+        let (_, trailers, _) = reader.into_parts();
+
+        let mut decoded = decompress::decode(&mut head.headers, ResponseBody::from(raw), None);
+        let mut body = Vec::new();
+        while let Some(chunk) = decoded.chunk().await? {
+            body.extend_from_slice(&chunk);
+        }
+        Ok(Self::response(head, ResponseBody::from(body).with_trailers(trailers), tls_info))
+    }
+
+    fn response(head: ResponseHead, body: ResponseBody, tls_info: Option<Arc<TlsInfo>>) -> Response {
+        Response {
+            version: head.version,
+            status_code: head.status.as_u16(),
+            status: head.status,
+            reason_phrase: head.reason,
+            headers: head.headers,
+            body,
+            raw_headers: head.raw_headers,
+            url: None,
+            redirects: Vec::new(),
+            informational: head.informational,
+            tls_info,
+        }
+    }
+}
+
+/// HTTP/1.1 connections persist unless closed explicitly; HTTP/1.0 ones only
+/// when the server opts in.
+fn is_keep_alive(head: &ResponseHead) -> bool {
+    if head.version == Version::HTTP_10 {
+        has_connection_token(&head.headers, "keep-alive")
+    } else {
+        !has_connection_close(&head.headers)
+    }
+}
 
-        if let Some(content_length) = content_length {
-            let mut remaining = content_length - body.len();
-            while remaining > 0 {
-                let mut buf = vec![0; remaining];
-                let n = stream.read(&mut buf).await?;
-                if n == 0 {
-                    break;
+fn has_connection_token(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+fn has_connection_close(headers: &HeaderMap) -> bool {
+    has_connection_token(headers, "close")
+}
+
+/// Tells pin failures apart from other handshake errors.
+fn handshake_error(host: &str, err: io::Error) -> Error {
+    let pin_failed = matches!(
+        err.get_ref().and_then(|inner| inner.downcast_ref::<rustls::Error>()),
+        Some(rustls::Error::InvalidCertificate(rustls::CertificateError::Other(other)))
+            if other.0.downcast_ref::<tls::PinMismatch>().is_some()
+    );
+    if pin_failed {
+        Error::CertificatePin { host: host.to_string() }
+    } else {
+        Error::TlsHandshake(err)
+    }
+}
+
+fn pool_key(url: &Url) -> Result<PoolKey, Error> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(Error::InvalidUrl(format!("unsupported scheme {}", url.scheme())));
+    }
+    PoolKey::from_url(url).ok_or_else(|| Error::InvalidUrl(format!("{} has no host or port", url)))
+}
+
+fn is_refused_stream(err: &Error) -> bool {
+    matches!(err, Error::Http2(reason) if *reason == Reason::REFUSED_STREAM)
+}
+
+fn is_stale_connection(err: &Error) -> bool {
+    match err {
+        Error::Io(err) => matches!(
+            err.kind(),
+            io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::StatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    struct TestServer {
+        url: Url,
+        accepted: Arc<AtomicUsize>,
+        requests: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl TestServer {
+        fn request_lines(&self) -> Vec<String> {
+            self.requests.lock().unwrap().iter().map(|r| r.lines().next().unwrap().to_string()).collect()
+        }
+    }
+
+    /// Length of the first complete request in `buf`, for requests framed by
+    /// `Content-Length` or without a body.
+    fn request_len(buf: &[u8]) -> Option<usize> {
+        let head_end = buf.windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+        let head = String::from_utf8_lossy(&buf[..head_end]).to_ascii_lowercase();
+        let body_len = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length:"))
+            .map_or(0, |len| len.trim().parse::<usize>().unwrap());
+        (buf.len() >= head_end + body_len).then_some(head_end + body_len)
+    }
+
+    /// Answers each request with the next canned response, recording
+    /// requests and counting accepted connections.
+    async fn serve(responses: Vec<&'static [u8]>) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (counter, log) = (accepted.clone(), requests.clone());
+        tokio::spawn(async move {
+            let mut responses = responses.into_iter();
+            while let Ok((mut socket, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut pending = Vec::new();
+                let mut buf = [0; 4096];
+                while let Ok(n) = socket.read(&mut buf).await {
+                    if n == 0 {
+                        break;
+                    }
+                    pending.extend_from_slice(&buf[..n]);
+                    while let Some(len) = request_len(&pending) {
+                        log.lock().unwrap().push(String::from_utf8_lossy(&pending[..len]).into_owned());
+                        pending.drain(..len);
+                        let Some(response) = responses.next() else { return };
+                        socket.write_all(response).await.unwrap();
+                    }
                 }
-                body.extend_from_slice(&buf[..n]);
-                remaining -= n;
             }
+        });
+        TestServer { url, accepted, requests }
+    }
+
+    /// An h2c server that echoes each request body back with the request
+    /// path in `x-path`, after a pause so that concurrent requests overlap.
+    async fn serve_http2() -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut conn = h2::server::handshake(socket).await.unwrap();
+                    while let Some(Ok((request, mut respond))) = conn.accept().await {
+                        tokio::spawn(async move {
+                            let (parts, mut body) = request.into_parts();
+                            let mut received = Vec::new();
+                            while let Some(chunk) = body.data().await {
+                                let chunk = chunk.unwrap();
+                                body.flow_control().release_capacity(chunk.len()).unwrap();
+                                received.extend_from_slice(&chunk);
+                            }
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            let response = http::Response::builder().header("x-path", parts.uri.path()).body(()).unwrap();
+                            let mut send = respond.send_response(response, false).unwrap();
+                            send.send_data(received.into(), true).unwrap();
+                        });
+                    }
+                });
+            }
+        });
+        TestServer {
+            url,
+            accepted,
+            requests: Default::default(),
         }
+    }
 
-        Ok(Response {
-            status_code,
-            status,
-            headers: headers_map,
-            body: Bytes::from(body).to_vec(),
-            reason_phrase: "".to_string(),
-        })
+    #[tokio::test]
+    async fn http2_prior_knowledge_multiplexes_requests() {
+        let server = serve_http2().await;
+        let client = Client::builder().http2_prior_knowledge().build().unwrap();
+        // Each body is larger than the default flow-control window.
+        let requests = (0..8u8).map(|i| {
+            client.post(server.url.join(&i.to_string()).unwrap()).body(vec![i; 100_000]).send()
+        });
+        let responses = futures_util::future::try_join_all(requests).await.unwrap();
+
+        for (i, response) in responses.into_iter().enumerate() {
+            assert_eq!(response.version, Version::HTTP_2);
+            assert_eq!(response.headers["x-path"], format!("/{}", i));
+            let body = response.bytes().await.unwrap();
+            assert_eq!(body.len(), 100_000);
+            assert!(body.iter().all(|&b| b == i as u8));
+        }
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 1);
+    }
+
+    /// A server that answers the first request on each connection, then
+    /// stops allowing streams with `SETTINGS_MAX_CONCURRENT_STREAMS = 0`.
+    #[tokio::test]
+    async fn http2_leaves_connections_that_allow_no_streams() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    socket.write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 0]).await.unwrap();
+                    let mut preface = [0; 24];
+                    socket.read_exact(&mut preface).await.unwrap();
+                    loop {
+                        let mut head = [0; 9];
+                        socket.read_exact(&mut head).await.unwrap();
+                        let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+                        socket.read_exact(&mut vec![0; len]).await.unwrap();
+                        if head[3] == 0x1 {
+                            break;
+                        }
+                    }
+                    let mut frames = vec![0, 0, 6, 4, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0];
+                    // HEADERS with END_STREAM | END_HEADERS: `:status: 200`.
+                    frames.extend_from_slice(&[0, 0, 1, 1, 0x5, 0, 0, 0, 1, 0x88]);
+                    socket.write_all(&frames).await.unwrap();
+                    while socket.read(&mut [0; 1024]).await.is_ok_and(|n| n > 0) {}
+                });
+            }
+        });
+
+        let timeouts = Timeouts {
+            total: Some(Duration::from_secs(5)),
+            ..Timeouts::default()
+        };
+        let client = Client::builder().http2_prior_knowledge().timeouts(timeouts).build().unwrap();
+        for _ in 0..2 {
+            let response = client.get(&url).send().await.unwrap();
+            assert_eq!(response.status, StatusCode::OK);
+        }
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+    }
+
+    /// Only idempotent requests are sent again after a reused connection
+    /// dies; the server may already have acted on a POST.
+    #[tokio::test]
+    async fn retries_only_idempotent_requests_on_stale_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut pending = Vec::new();
+                let mut buf = [0; 4096];
+                'conn: while let Ok(n) = socket.read(&mut buf).await {
+                    if n == 0 {
+                        break;
+                    }
+                    pending.extend_from_slice(&buf[..n]);
+                    while let Some(len) = request_len(&pending) {
+                        let line = String::from_utf8_lossy(&pending[..len]).lines().next().unwrap().to_string();
+                        let count = {
+                            let mut log = log.lock().unwrap();
+                            log.push(line);
+                            log.len()
+                        };
+                        pending.drain(..len);
+                        // Every second request finds the connection dropped.
+                        if count % 2 == 0 {
+                            break 'conn;
+                        }
+                        socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+                    }
+                }
+            }
+        });
+
+        let client = Client::new();
+        client.get(&url).send().await.unwrap();
+        assert!(client.post(&url).body("x").send().await.is_err());
+        client.get(&url).send().await.unwrap();
+        client.put(&url).body("x").send().await.unwrap();
+        assert_eq!(*requests.lock().unwrap(), ["GET / HTTP/1.1", "POST / HTTP/1.1", "GET / HTTP/1.1", "PUT / HTTP/1.1", "PUT / HTTP/1.1"]);
+    }
+
+    #[tokio::test]
+    async fn plain_http_round_trip() {
+        let server = serve(vec![b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"]).await;
+        let client = Client::new();
+        let response = client.get(server.url.join("hello").unwrap()).send().await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn reuses_keep_alive_connections_until_close() {
+        let server = serve(vec![
+            b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\na",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\nConnection: close\r\n\r\nb",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\nc",
+        ])
+        .await;
+        let client = Client::new();
+        for expected in [b"a", b"b", b"c"] {
+            let response = client.get(&server.url).send().await.unwrap();
+            assert_eq!(response.bytes().await.unwrap(), &expected[..]);
+        }
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn bodiless_and_interim_responses_keep_the_connection_in_sync() {
+        let server = serve(vec![
+            b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n",
+            b"HTTP/1.1 304 Not Modified\r\nContent-Length: 100\r\n\r\n",
+            b"HTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+        ])
+        .await;
+        let client = Client::new();
+
+        let response = client.head(&server.url).send().await.unwrap();
+        assert_eq!(response.headers["content-length"], "100");
+        assert!(response.bytes().await.unwrap().is_empty());
+        let response = client.get(&server.url).send().await.unwrap();
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+        assert!(response.bytes().await.unwrap().is_empty());
+
+        let response = client.get(&server.url).send().await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.informational().len(), 1);
+        assert_eq!(response.informational()[0].status, StatusCode::EARLY_HINTS);
+        assert_eq!(response.bytes().await.unwrap(), "ok");
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn streams_body_and_returns_connection_when_consumed() {
+        let server = serve(vec![
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n3\r\ndef\r\n0\r\nX-Sum: 6\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+        ])
+        .await;
+        let client = Client::new();
+
+        let mut response = client.get(&server.url).send().await.unwrap();
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.unwrap() {
+            body.extend_from_slice(&chunk);
+        }
+        assert_eq!(body, b"abcdef");
+        assert_eq!(response.trailers().unwrap()["x-sum"], "6");
+
+        let mut text = String::new();
+        let response = client.get(&server.url).send().await.unwrap();
+        response.body.take(1024).read_to_string(&mut text).await.unwrap();
+        assert_eq!(text, "ok");
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn decodes_gzip_unless_disabled() {
+        use async_compression::tokio::write::GzipEncoder;
+        let mut encoder = GzipEncoder::new(Vec::new());
+        encoder.write_all(b"hello gzip").await.unwrap();
+        encoder.shutdown().await.unwrap();
+        let encoded = encoder.into_inner();
+        let mut response = format!("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n", encoded.len()).into_bytes();
+        response.extend_from_slice(&encoded);
+        let response: &'static [u8] = Box::leak(response.into_boxed_slice());
+        let server = serve(vec![response, response]).await;
+
+        let decoded = Client::new().get(&server.url).send().await.unwrap();
+        assert!(!decoded.headers.contains_key(http::header::CONTENT_ENCODING));
+        assert_eq!(decoded.text().await.unwrap(), "hello gzip");
+
+        let raw = Client::builder().decompress(false).build().unwrap().get(&server.url).send().await.unwrap();
+        assert_eq!(raw.bytes().await.unwrap(), encoded);
+
+        let requests = server.requests.lock().unwrap();
+        assert!(requests[0].to_ascii_lowercase().contains("accept-encoding: gzip"));
+        assert!(!requests[1].to_ascii_lowercase().contains("accept-encoding"));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn limits_decoded_body_size() {
+        use async_compression::tokio::write::GzipEncoder;
+        let mut encoder = GzipEncoder::new(Vec::new());
+        encoder.write_all(&vec![0; 1 << 20]).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        let encoded = encoder.into_inner();
+        assert!(encoded.len() < 4096);
+        let mut response = format!("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n", encoded.len()).into_bytes();
+        response.extend_from_slice(&encoded);
+        let server = serve(vec![Box::leak(response.into_boxed_slice())]).await;
+
+        let client = Client::builder().max_response_body_size(64 * 1024).build().unwrap();
+        let response = client.get(&server.url).send().await.unwrap();
+        let err = response.bytes().await.unwrap_err();
+        assert!(matches!(err, Error::BodyTooLarge { limit: 65536 }));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn request_timeouts_override_client_defaults() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let client = Client::builder()
+            .timeouts(Timeouts {
+                first_byte: Some(Duration::from_millis(50)),
+                ..Timeouts::default()
+            })
+            .build()
+            .unwrap();
+        let err = client.get(&url).send().await.unwrap_err();
+        assert!(err.is_timeout());
+        assert!(matches!(err, Error::Timeout(TimeoutKind::FirstByte)));
+
+        let timeouts = Timeouts {
+            total: Some(Duration::from_millis(20)),
+            first_byte: Some(Duration::from_secs(60)),
+            ..Timeouts::default()
+        };
+        let err = client.get(&url).timeouts(timeouts).send().await.unwrap_err();
+        assert!(matches!(err, Error::Timeout(TimeoutKind::Total)));
+    }
+
+    #[tokio::test]
+    async fn expect_continue_holds_the_body_until_the_server_agrees() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let server = tokio::spawn(async move {
+            async fn read_head(socket: &mut TcpStream) -> Vec<u8> {
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    head.push(socket.read_u8().await.unwrap());
+                }
+                head
+            }
+
+            let (mut socket, _) = listener.accept().await.unwrap();
+            assert!(String::from_utf8(read_head(&mut socket).await).unwrap().contains("Expect: 100-continue\r\n"));
+            socket.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await.unwrap();
+            let mut body = [0; 5];
+            socket.read_exact(&mut body).await.unwrap();
+            socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n").await.unwrap();
+            socket.write_all(&body).await.unwrap();
+
+            // A rejection comes before the body, which must then never arrive.
+            read_head(&mut socket).await;
+            socket.write_all(b"HTTP/1.1 413 Content Too Large\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+            let mut rest = Vec::new();
+            socket.read_to_end(&mut rest).await.unwrap();
+            assert!(rest.is_empty());
+        });
+
+        let client = Client::new();
+        let response = client.put(&url).expect_continue().body("hello").send().await.unwrap();
+        assert_eq!(response.informational()[0].status, StatusCode::CONTINUE);
+        assert_eq!(response.bytes().await.unwrap(), "hello");
+
+        let response = client.put(&url).expect_continue().body("hello").send().await.unwrap();
+        assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+        drop(response);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn expect_continue_sends_the_body_after_the_timeout() {
+        let server = serve(vec![b"HTTP/1.1 204 No Content\r\n\r\n"]).await;
+        let client = Client::builder()
+            .timeouts(Timeouts {
+                expect_continue: Some(Duration::from_millis(20)),
+                ..Timeouts::default()
+            })
+            .build()
+            .unwrap();
+        let response = client.post(&server.url).expect_continue().body("late").send().await.unwrap();
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert!(server.requests.lock().unwrap()[0].ends_with("\r\n\r\nlate"));
+    }
+
+    #[tokio::test]
+    async fn cookie_jar_carries_cookies_across_redirects() {
+        let server = serve(vec![
+            b"HTTP/1.1 302 Found\r\nLocation: /next\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2; Path=/next\r\nContent-Length: 0\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+        ])
+        .await;
+        let jar = Arc::new(CookieJar::new());
+        let client = Client::builder().cookie_jar(jar.clone()).build().unwrap();
+        client.get(&server.url).send().await.unwrap();
+        client.get(&server.url).header("Cookie", "mine=1").send().await.unwrap();
+
+        let requests = server.requests.lock().unwrap().clone();
+        assert!(!requests[0].to_ascii_lowercase().contains("cookie"));
+        assert!(requests[1].contains("\r\ncookie: b=2; a=1\r\n"));
+        assert!(requests[2].contains("\r\nCookie: mine=1\r\n") && !requests[2].contains("a=1"));
+        assert_eq!(jar.cookies().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn forwards_http_and_tunnels_https_through_proxies() {
+        let server = serve(vec![
+            b"HTTP/1.1 200 OK\r\nContent-Length: 7\r\nConnection: close\r\n\r\nproxied",
+            b"HTTP/1.1 407 Proxy Authentication Required\r\nContent-Length: 0\r\n\r\n",
+        ])
+        .await;
+        let proxy = Proxy::all(server.url.as_str()).unwrap().basic_auth("user", "secret").no_proxy("localhost");
+        let client = Client::builder().proxy(proxy).build().unwrap();
+
+        let response = client.get("http://example.invalid/path?q=1").send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "proxied");
+        let err = client.get("https://example.invalid/").send().await.unwrap_err();
+        assert!(matches!(err, Error::ProxyConnect(StatusCode::PROXY_AUTHENTICATION_REQUIRED)));
+        assert!(err.is_connect());
+
+        let requests = server.requests.lock().unwrap().clone();
+        assert!(requests[0].starts_with("GET http://example.invalid/path?q=1 HTTP/1.1\r\nHost: example.invalid\r\n"));
+        assert!(requests[0].contains("\r\nproxy-authorization: Basic dXNlcjpzZWNyZXQ=\r\n"));
+        assert!(requests[1].starts_with("CONNECT example.invalid:443 HTTP/1.1\r\nHost: example.invalid:443\r\n"));
+        assert!(requests[1].contains("\r\nproxy-authorization: Basic dXNlcjpzZWNyZXQ=\r\n"));
+    }
+
+    /// A self-signed certificate for `names` and a server config presenting it.
+    fn self_signed(names: &[&str]) -> (Certificate, Arc<rustls::ServerConfig>) {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let names = names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        let cert = rcgen::CertificateParams::new(names).unwrap().self_signed(&key).unwrap();
+        let config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.der().clone()], key.serialize_der().try_into().unwrap())
+            .unwrap();
+        (Certificate::from_der(cert.der().to_vec()), Arc::new(config))
+    }
+
+    /// Answers every request on every connection with "secret". `host` is
+    /// `localhost` or `[::1]`.
+    async fn serve_tls(config: Arc<rustls::ServerConfig>, host: &str) -> String {
+        let acceptor = tokio_rustls::TlsAcceptor::from(config);
+        let bind = if host == "localhost" { "127.0.0.1:0" } else { "[::1]:0" };
+        let listener = TcpListener::bind(bind).await.unwrap();
+        let url = format!("https://{}:{}/", host, listener.local_addr().unwrap().port());
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(socket).await else { return };
+                    let mut buf = [0; 1024];
+                    if stream.read(&mut buf).await.unwrap_or(0) > 0 {
+                        let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nsecret").await;
+                    }
+                });
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn pins_server_keys_and_can_skip_verification() {
+        let (certificate, server_config) = self_signed(&["localhost"]);
+        let url = serve_tls(server_config, "localhost").await;
+        let pin = certificate.spki_sha256().unwrap();
+        let trusting = || Client::builder().tls_built_in_roots(false).add_root_certificate(certificate.clone());
+
+        let client = trusting().pin_public_key("LOCALHOST", [0; 32]).pin_public_key("localhost", pin).build().unwrap();
+        assert_eq!(client.get(&url).send().await.unwrap().text().await.unwrap(), "secret");
+        // Pins are hashed with the provider's SHA-256 even when no allowed suite uses it.
+        let sha384_only = trusting().tls_cipher_suites(&[rustls::CipherSuite::TLS13_AES_256_GCM_SHA384]);
+        let client = sha384_only.pin_public_key("localhost", pin).build().unwrap();
+        assert_eq!(client.get(&url).send().await.unwrap().text().await.unwrap(), "secret");
+
+        let err = trusting().pin_public_key("localhost", [0; 32]).build().unwrap().get(&url).send().await.unwrap_err();
+        assert!(matches!(&err, Error::CertificatePin { host } if host == "localhost"), "{:?}", err);
+        assert!(err.is_connect());
+        let other_host = trusting().pin_public_key("example.com", [0; 32]).build().unwrap();
+        assert!(other_host.get(&url).send().await.is_ok());
+
+        let err = Client::new().get(&url).send().await.unwrap_err();
+        assert!(matches!(err, Error::TlsHandshake(_)), "{:?}", err);
+        let client = Client::builder().danger_accept_invalid_certs().build().unwrap();
+        assert_eq!(client.get(&url).send().await.unwrap().text().await.unwrap(), "secret");
+        let client = Client::builder().danger_accept_invalid_certs().pin_public_key("localhost", [1; 32]).build().unwrap();
+        assert!(matches!(client.get(&url).send().await, Err(Error::CertificatePin { .. })));
+    }
+
+    #[tokio::test]
+    async fn reports_tls_session_details() {
+        let (cert, mut server_config) = self_signed(&["localhost"]);
+        Arc::get_mut(&mut server_config).unwrap().alpn_protocols = vec![b"http/1.1".to_vec()];
+        let url = serve_tls(server_config, "localhost").await;
+        let client = Client::builder()
+            .tls_built_in_roots(false)
+            .add_root_certificate(cert.clone())
+            .pool_idle_timeout(Some(Duration::ZERO))
+            .build()
+            .unwrap();
+
+        let response = client.get(&url).send().await.unwrap();
+        let info = response.tls_info().unwrap().clone();
+        assert_eq!(response.text().await.unwrap(), "secret");
+        assert_eq!(info.version(), TlsVersion::Tls13);
+        assert!(info.cipher_suite().as_str().unwrap().starts_with("TLS13_"));
+        assert_eq!(info.alpn_protocol(), Some(&b"http/1.1"[..]));
+        assert_eq!(info.peer_certificates().len(), 1);
+        assert_eq!(info.peer_certificates()[0].as_der(), cert.as_der());
+        assert!(!info.resumed());
+
+        let response = client.get(&url).send().await.unwrap();
+        assert!(response.tls_info().unwrap().resumed());
+        assert_eq!(response.tls_info().unwrap().peer_certificates()[0].as_der(), cert.as_der());
+
+        let plain = serve(vec![b"HTTP/1.1 204 No Content\r\n\r\n"]).await;
+        assert!(Client::new().get(&plain.url).send().await.unwrap().tls_info().is_none());
+    }
+
+    #[tokio::test]
+    async fn resumes_sessions_and_sends_idempotent_requests_as_early_data() {
+        use std::io::Read;
+
+        let (cert, mut server_config) = self_signed(&["localhost"]);
+        let config = Arc::get_mut(&mut server_config).unwrap();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        config.max_early_data_size = 4096;
+        let acceptor = tokio_rustls::TlsAcceptor::from(server_config);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://localhost:{}/", listener.local_addr().unwrap().port());
+        // Answers with the start of the request line and whether it came as early data.
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let Ok(mut stream) = acceptor.accept(socket).await else { continue };
+                let mut buf = vec![0; 4096];
+                let early = stream.get_mut().1.early_data().map_or(0, |mut data| data.read(&mut buf).unwrap_or(0));
+                let (n, kind) = if early > 0 { (early, "early") } else { (stream.read(&mut buf).await.unwrap(), "late") };
+                let method = String::from_utf8_lossy(&buf[..n]).split(' ').next().unwrap().to_string();
+                let reply = if method == "DELETE" && early > 0 {
+                    "HTTP/1.1 425 Too Early\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+                } else {
+                    let body = format!("{} {}", method, kind);
+                    format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+                };
+                let _ = stream.write_all(reply.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+
+        let client = Client::builder()
+            .tls_built_in_roots(false)
+            .add_root_certificate(cert.clone())
+            .tls_session_cache_size(64)
+            .tls_early_data(true)
+            .build()
+            .unwrap();
+        let text = |response: Response| response.text();
+        assert_eq!(text(client.get(&url).send().await.unwrap()).await.unwrap(), "GET late");
+        let response = client.get(&url).send().await.unwrap();
+        assert!(response.tls_info().unwrap().resumed() && response.tls_info().unwrap().early_data());
+        assert_eq!(text(response).await.unwrap(), "GET early");
+        assert_eq!(text(client.post(&url).send().await.unwrap()).await.unwrap(), "POST late");
+        assert_eq!(text(client.delete(&url).send().await.unwrap()).await.unwrap(), "DELETE late");
+        assert_eq!(
+            client.tls_stats(),
+            TlsStats { handshakes: 5, resumed: 4, early_data_sent: 2, early_data_accepted: 2 }
+        );
+
+        let uncached = Client::builder()
+            .tls_built_in_roots(false)
+            .add_root_certificate(cert)
+            .tls_session_cache_size(0)
+            .build()
+            .unwrap();
+        uncached.get(&url).send().await.unwrap();
+        assert!(!uncached.get(&url).send().await.unwrap().tls_info().unwrap().resumed());
+        assert_eq!(uncached.tls_stats().resumed, 0);
+    }
+
+    #[tokio::test]
+    async fn trusts_private_roots_and_presents_client_certificates() {
+        use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+        use rustls::server::WebPkiClientVerifier;
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let server_key = KeyPair::generate().unwrap();
+        let server_cert = CertificateParams::new(vec!["localhost".to_string()]).unwrap().signed_by(&server_key, &ca, &ca_key).unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let mut client_params = CertificateParams::new(vec!["client".to_string()]).unwrap();
+        client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let client_cert = client_params.signed_by(&client_key, &ca, &ca_key).unwrap();
+
+        let mut client_roots = rustls::RootCertStore::empty();
+        client_roots.add(ca.der().clone()).unwrap();
+        let verifier = WebPkiClientVerifier::builder(Arc::new(client_roots)).build().unwrap();
+        let server_config = rustls::ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(vec![server_cert.der().clone()], server_key.serialize_der().try_into().unwrap())
+            .unwrap();
+        let url = serve_tls(Arc::new(server_config), "localhost").await;
+
+        let builder = || {
+            Client::builder()
+                .tls_built_in_roots(false)
+                .add_root_certificate(Certificate::from_pem(ca.pem().as_bytes()).unwrap())
+                .min_tls_version(TlsVersion::Tls13)
+        };
+        let identity = Identity::from_pem(client_cert.pem().as_bytes(), client_key.serialize_pem().as_bytes()).unwrap();
+        let client = builder()
+            .identity(identity)
+            .tls_cipher_suites(&[rustls::CipherSuite::TLS13_AES_256_GCM_SHA384])
+            .build()
+            .unwrap();
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "secret");
+
+        assert!(builder().build().unwrap().get(&url).send().await.is_err());
+        assert!(Client::new().get(&url).send().await.unwrap_err().is_connect());
+        let err = builder().tls_cipher_suites(&[rustls::CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256]).build();
+        assert!(matches!(err, Err(Error::TlsConfig(_))));
+    }
+
+    #[tokio::test]
+    async fn logs_tls_secrets_only_when_asked() {
+        use crate::tls::KeyLogFn;
+
+        let (cert, server_config) = self_signed(&["localhost"]);
+        let url = serve_tls(server_config, "localhost").await;
+        let logged = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = logged.clone();
+        let client = Client::builder()
+            .tls_built_in_roots(false)
+            .add_root_certificate(cert)
+            .key_log(Arc::new(KeyLogFn::new(move |label: &str, client_random: &[u8], secret: &[u8]| {
+                sink.lock().unwrap().push((label.to_string(), client_random.to_vec(), secret.len()));
+            })))
+            .build()
+            .unwrap();
+        assert_eq!(client.get(&url).send().await.unwrap().text().await.unwrap(), "secret");
+
+        let logged = logged.lock().unwrap();
+        let labels: Vec<&str> = logged.iter().map(|(label, _, _)| label.as_str()).collect();
+        assert!(labels.contains(&"CLIENT_HANDSHAKE_TRAFFIC_SECRET") && labels.contains(&"CLIENT_TRAFFIC_SECRET_0"));
+        assert!(logged.iter().all(|(_, client_random, len)| *client_random == logged[0].1 && client_random.len() == 32 && *len > 0));
+
+        assert_eq!(format!("{:?}", Client::new().tls_config.key_log), "NoKeyLog");
+        let file = Client::builder().key_log_file().build().unwrap();
+        assert!(format!("{:?}", file.tls_config.key_log).starts_with("KeyLogFile"));
+    }
+
+    #[tokio::test]
+    async fn connects_to_ipv6_literals() {
+        let listener = TcpListener::bind("[::1]:0").await.unwrap();
+        let url = format!("http://[::1]:{}/", listener.local_addr().unwrap().port());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            assert!(socket.read(&mut buf).await.unwrap() > 0);
+            socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nv6").await.unwrap();
+        });
+        let response = Client::new().get(&url).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "v6");
+    }
+
+    #[tokio::test]
+    async fn verifies_certificates_for_ip_literals() {
+        let (cert, server_config) = self_signed(&["::1"]);
+        let url = serve_tls(server_config, "[::1]").await;
+        let client = Client::builder().tls_built_in_roots(false).add_root_certificate(cert).build().unwrap();
+        assert_eq!(client.get(&url).send().await.unwrap().text().await.unwrap(), "secret");
+    }
+
+    #[tokio::test]
+    async fn reports_connect_and_protocol_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        drop(listener);
+        let err = Client::new().get(&url).send().await.unwrap_err();
+        assert!(err.is_connect());
+
+        let server = serve(vec![b"HTTP/1.1 OK\r\n\r\n"]).await;
+        let err = Client::new().get(&server.url).send().await.unwrap_err();
+        assert!(matches!(err, Error::MalformedStatusLine(_)));
+    }
+
+    #[tokio::test]
+    async fn follows_redirects_rewriting_post_to_get() {
+        let server = serve(vec![
+            b"HTTP/1.1 302 Found\r\nLocation: /next?x=1\r\nContent-Length: 0\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\ndone",
+        ])
+        .await;
+        let response = Client::new()
+            .post(server.url.join("start").unwrap())
+            .header("Content-Type", "text/plain")
+            .body("payload")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.url(), Some(&server.url.join("next?x=1").unwrap()));
+        assert_eq!(response.redirects(), [server.url.join("start").unwrap()]);
+        assert_eq!(response.bytes().await.unwrap(), "done");
+        assert_eq!(server.request_lines(), ["POST /start HTTP/1.1", "GET /next?x=1 HTTP/1.1"]);
+        assert!(!server.requests.lock().unwrap()[1].contains("payload"));
+    }
+
+    #[tokio::test]
+    async fn redirect_policy_limits_and_loops() {
+        let server = serve(vec![b"HTTP/1.1 301 Moved\r\nLocation: /a\r\nContent-Length: 0\r\n\r\n"; 2]).await;
+        let err = Client::new().get(server.url.join("a").unwrap()).send().await.unwrap_err();
+        assert!(matches!(err, Error::RedirectLoop(_)));
+
+        let server = serve(vec![b"HTTP/1.1 307 Moved\r\nLocation: /b\r\nContent-Length: 0\r\n\r\n"]).await;
+        let client = Client::builder().redirect(Policy::None).build().unwrap();
+        let response = client.get(&server.url).send().await.unwrap();
+        assert_eq!(response.status, StatusCode::TEMPORARY_REDIRECT);
+        assert!(response.redirects().is_empty());
+    }
+
+    #[tokio::test]
+    async fn custom_redirect_policies_are_capped() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut n = 0;
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = [0; 1024];
+                assert!(socket.read(&mut buf).await.unwrap() > 0);
+                n += 1;
+                let response = format!("HTTP/1.1 302 Found\r\nLocation: /?n={n}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        let client = Client::builder().redirect(Policy::custom(|_| Action::Follow)).build().unwrap();
+        let err = client.get(&url).send().await.unwrap_err();
+        assert!(matches!(err, Error::TooManyRedirects(MAX_REDIRECTS)));
+    }
+}
@@ -0,0 +1,588 @@
+//! A cookie store following RFC 6265: `Set-Cookie` parsing, the storage
+//! model of section 5.3 and the `Cookie` header of section 5.4.
+
+use crate::Error;
+use http::header::SET_COOKIE;
+use http::{HeaderMap, HeaderValue};
+use std::collections::HashSet;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// Cookies shared by every request of a `Client` built with
+/// `ClientBuilder::cookie_jar`.
+///
+/// Without a public suffix list the jar cannot tell `example.com` from
+/// `co.uk`, so a `Domain` attribute naming a parent of the host is not
+/// trusted: the cookie is kept for the host that set it alone, and one for a
+/// top-level domain is refused. Load the list with `with_public_suffix_list`
+/// to let sites share cookies with their subdomains.
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    cookies: Mutex<Vec<Cookie>>,
+    suffixes: Option<PublicSuffixList>,
+}
+
+/// A stored cookie.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// The canonical host, without a leading dot.
+    pub domain: String,
+    /// Set when the cookie had no `Domain` attribute: only `domain` itself
+    /// gets it back, not its subdomains.
+    pub host_only: bool,
+    pub path: String,
+    /// `None` for a session cookie.
+    pub expires: Option<SystemTime>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+    created: SystemTime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl CookieJar {
+    /// An empty jar without a public suffix list. `Domain` attributes that
+    /// name a parent of the host are not honoured: `Domain=example.com` set
+    /// by `www.example.com` stays with `www.example.com`, and
+    /// `api.example.com` never gets it.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses the rules of a public suffix list in the format published at
+    /// <https://publicsuffix.org/list/>.
+    pub fn with_public_suffix_list(mut self, list: &str) -> Self {
+        self.suffixes = Some(PublicSuffixList::parse(list));
+        self
+    }
+
+    /// Stores the cookie from one `Set-Cookie` value received from `url`.
+    /// Values that are malformed or not allowed for `url` are ignored, as
+    /// RFC 6265 requires.
+    pub fn store(&self, url: &Url, set_cookie: &str) {
+        let now = SystemTime::now();
+        let Some(cookie) = self.parse(url, set_cookie, now) else { return };
+        self.insert(cookie, now);
+    }
+
+    pub(crate) fn store_response(&self, url: &Url, headers: &HeaderMap) {
+        for value in headers.get_all(SET_COOKIE) {
+            if let Ok(value) = std::str::from_utf8(value.as_bytes()) {
+                self.store(url, value);
+            }
+        }
+    }
+
+    /// The `Cookie` header value for a request to `url`, if any cookie matches.
+    pub fn header(&self, url: &Url) -> Option<HeaderValue> {
+        let host = canonical_host(url)?;
+        let secure = matches!(url.scheme(), "https" | "wss");
+        let now = SystemTime::now();
+        let mut cookies = self.cookies.lock().unwrap();
+        cookies.retain(|cookie| !cookie.is_expired(now));
+        let mut matching: Vec<&Cookie> = cookies
+            .iter()
+            .filter(|cookie| {
+                let host_matches = if cookie.host_only { host == cookie.domain } else { domain_match(&host, &cookie.domain) };
+                host_matches && path_match(url.path(), &cookie.path) && (secure || !cookie.secure)
+            })
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+        // Longer paths first, then older cookies first (section 5.4, step 2).
+        matching.sort_by(|a, b| b.path.len().cmp(&a.path.len()).then(a.created.cmp(&b.created)));
+        let pairs: Vec<String> = matching.iter().map(|cookie| format!("{}={}", cookie.name, cookie.value)).collect();
+        HeaderValue::try_from(pairs.join("; ")).ok()
+    }
+
+    /// Every cookie that has not expired.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        let now = SystemTime::now();
+        self.cookies.lock().unwrap().iter().filter(|cookie| !cookie.is_expired(now)).cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.cookies.lock().unwrap().clear();
+    }
+
+    /// Writes every cookie that has not expired, session cookies included,
+    /// in the Netscape `cookies.txt` format that curl and wget also read.
+    /// `SameSite` has no column there and is not kept.
+    pub async fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        tokio::fs::write(path, self.to_netscape()).await?;
+        Ok(())
+    }
+
+    /// Adds the cookies from a file written by `save`, replacing stored
+    /// cookies with the same name, domain and path.
+    pub async fn load<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let text = tokio::fs::read_to_string(path).await?;
+        self.load_netscape(&text)
+    }
+
+    fn to_netscape(&self) -> String {
+        let mut out = String::from("# Netscape HTTP Cookie File\n");
+        for cookie in self.cookies() {
+            let expires = cookie.expires.map_or(0, |expires| {
+                expires.duration_since(UNIX_EPOCH).map_or(1, |since| since.as_secs().max(1))
+            });
+            out.push_str(&format!(
+                "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                if cookie.http_only { "#HttpOnly_" } else { "" },
+                if cookie.host_only { "" } else { "." },
+                cookie.domain,
+                if cookie.host_only { "FALSE" } else { "TRUE" },
+                cookie.path,
+                if cookie.secure { "TRUE" } else { "FALSE" },
+                expires,
+                cookie.name,
+                cookie.value,
+            ));
+        }
+        out
+    }
+
+    fn load_netscape(&self, text: &str) -> Result<(), Error> {
+        let now = SystemTime::now();
+        for (number, line) in text.lines().enumerate() {
+            let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || Error::Io(io::Error::new(io::ErrorKind::InvalidData, format!("invalid cookie on line {}", number + 1)));
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [domain, subdomains, path, secure, expires, name, value] = fields[..] else { return Err(invalid()) };
+            let expires: u64 = expires.parse().map_err(|_| invalid())?;
+            let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+            if domain.is_empty() || name.is_empty() {
+                return Err(invalid());
+            }
+            let cookie = Cookie {
+                name: name.to_string(),
+                value: value.to_string(),
+                domain,
+                host_only: !subdomains.eq_ignore_ascii_case("TRUE"),
+                path: path.to_string(),
+                expires: (expires > 0).then(|| UNIX_EPOCH + Duration::from_secs(expires)),
+                secure: secure.eq_ignore_ascii_case("TRUE"),
+                http_only,
+                same_site: None,
+                created: now,
+            };
+            self.insert(cookie, now);
+        }
+        Ok(())
+    }
+
+    /// Section 5.2 parsing combined with the checks of section 5.3.
+    fn parse(&self, url: &Url, set_cookie: &str, now: SystemTime) -> Option<Cookie> {
+        let host = canonical_host(url)?;
+        let mut parts = set_cookie.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+            created: now,
+        };
+        let mut max_age = None;
+        let mut domain = None;
+        for attribute in parts {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let (key, value) = (key.trim(), value.trim());
+            if key.eq_ignore_ascii_case("expires") {
+                if let Some(expires) = parse_date(value) {
+                    cookie.expires = Some(expires);
+                }
+            } else if key.eq_ignore_ascii_case("max-age") {
+                let digits = value.strip_prefix('-').unwrap_or(value);
+                if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+                    // Anything too large to represent is as good as forever.
+                    max_age = Some(value.parse::<i64>().unwrap_or(if value.starts_with('-') { -1 } else { i64::MAX }));
+                }
+            } else if key.eq_ignore_ascii_case("domain") {
+                let value = value.strip_prefix('.').unwrap_or(value);
+                if !value.is_empty() {
+                    domain = Some(value.to_ascii_lowercase());
+                }
+            } else if key.eq_ignore_ascii_case("path") {
+                if value.starts_with('/') {
+                    cookie.path = value.to_string();
+                }
+            } else if key.eq_ignore_ascii_case("secure") {
+                cookie.secure = true;
+            } else if key.eq_ignore_ascii_case("httponly") {
+                cookie.http_only = true;
+            } else if key.eq_ignore_ascii_case("samesite") {
+                cookie.same_site = match value.to_ascii_lowercase().as_str() {
+                    "strict" => Some(SameSite::Strict),
+                    "lax" => Some(SameSite::Lax),
+                    "none" => Some(SameSite::None),
+                    _ => cookie.same_site,
+                };
+            }
+        }
+        // Max-Age wins over Expires, wherever the two appear.
+        match max_age {
+            Some(seconds) if seconds <= 0 => cookie.expires = Some(UNIX_EPOCH),
+            Some(seconds) => cookie.expires = Some(now.checked_add(Duration::from_secs(seconds as u64)).unwrap_or(far_future())),
+            None => {}
+        }
+
+        if let Some(domain) = domain {
+            let public_suffix = match &self.suffixes {
+                Some(suffixes) => suffixes.is_public_suffix(&domain),
+                // The list's implicit `*` rule.
+                None => !domain.contains('.'),
+            };
+            if public_suffix {
+                // A site may set a cookie for itself even if it is a public
+                // suffix, but never for everything under one.
+                if domain != host {
+                    return None;
+                }
+            } else if !domain_match(&host, &domain) {
+                return None;
+            } else if self.suffixes.is_some() || domain == host {
+                cookie.domain = domain;
+                cookie.host_only = false;
+            }
+        }
+        // Only secure origins may set secure cookies (RFC 6265bis, section 5.7).
+        if cookie.secure && !matches!(url.scheme(), "https" | "wss") {
+            return None;
+        }
+        Some(cookie)
+    }
+
+    /// Replaces a cookie with the same name, domain and path, keeping its
+    /// creation time. An expired cookie only removes the old one.
+    fn insert(&self, mut cookie: Cookie, now: SystemTime) {
+        let mut cookies = self.cookies.lock().unwrap();
+        let existing = cookies
+            .iter()
+            .position(|old| old.name == cookie.name && old.domain == cookie.domain && old.path == cookie.path);
+        if let Some(index) = existing {
+            cookie.created = cookies.remove(index).created;
+        }
+        if !cookie.is_expired(now) {
+            cookies.push(cookie);
+        }
+    }
+}
+
+impl Cookie {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// Public suffix rules, in the format of the Public Suffix List.
+#[derive(Debug, Default)]
+struct PublicSuffixList {
+    rules: HashSet<String>,
+    /// `*.example` rules, stored as `example`.
+    wildcards: HashSet<String>,
+    /// `!www.example` rules, stored as `www.example`.
+    exceptions: HashSet<String>,
+}
+
+impl PublicSuffixList {
+    fn parse(list: &str) -> Self {
+        let mut suffixes = PublicSuffixList::default();
+        for line in list.lines() {
+            let Some(rule) = line.split_whitespace().next().filter(|rule| !rule.starts_with("//")) else { continue };
+            let (set, rule) = if let Some(rule) = rule.strip_prefix('!') {
+                (&mut suffixes.exceptions, rule)
+            } else if let Some(rule) = rule.strip_prefix("*.") {
+                (&mut suffixes.wildcards, rule)
+            } else {
+                (&mut suffixes.rules, rule)
+            };
+            // The list is in Unicode; hosts in URLs are already punycode.
+            if let Ok(url::Host::Domain(domain)) = url::Host::parse(rule) {
+                set.insert(domain);
+            }
+        }
+        suffixes
+    }
+
+    /// The algorithm from <https://publicsuffix.org/list/>: the longest
+    /// matching rule wins, exceptions beat everything, and a domain no rule
+    /// matches falls under the implicit `*` rule.
+    fn public_suffix<'a>(&self, domain: &'a str) -> &'a str {
+        let mut suffix = domain;
+        loop {
+            let parent = suffix.split_once('.').map(|(_, parent)| parent);
+            if self.exceptions.contains(suffix) {
+                return parent.unwrap_or(suffix);
+            }
+            if self.rules.contains(suffix) || parent.is_some_and(|parent| self.wildcards.contains(parent)) {
+                return suffix;
+            }
+            match parent {
+                Some(parent) => suffix = parent,
+                None => return suffix,
+            }
+        }
+    }
+
+    fn is_public_suffix(&self, domain: &str) -> bool {
+        self.public_suffix(domain) == domain
+    }
+}
+
+fn canonical_host(url: &Url) -> Option<String> {
+    match url.host()? {
+        url::Host::Domain(domain) => Some(domain.trim_end_matches('.').to_ascii_lowercase()),
+        url::Host::Ipv4(ip) => Some(ip.to_string()),
+        url::Host::Ipv6(ip) => Some(ip.to_string()),
+    }
+}
+
+/// Section 5.1.3: `domain` is `host` or a parent domain of a host name.
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.len() > domain.len()
+            && host.ends_with(domain)
+            && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+            && host.parse::<IpAddr>().is_err())
+}
+
+/// Section 5.1.4: the directory of the request path.
+fn default_path(url: &Url) -> String {
+    match url.path().rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(slash) => url.path()[..slash].to_string(),
+    }
+}
+
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path.as_bytes()[cookie_path.len()] == b'/'))
+}
+
+fn far_future() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(253_402_300_799)
+}
+
+/// The lenient date parser of section 5.1.1, which accepts the many formats
+/// found in `Expires` attributes.
+fn parse_date(value: &str) -> Option<SystemTime> {
+    let is_delimiter = |c: char| matches!(c, '\t' | ' '..='/' | ';'..='@' | '['..='`' | '{'..='~');
+    let (mut time, mut day, mut month, mut year) = (None, None, None, None);
+    for token in value.split(is_delimiter).filter(|token| !token.is_empty()) {
+        if time.is_none() {
+            if let Some(parsed) = parse_time(token) {
+                time = Some(parsed);
+                continue;
+            }
+        }
+        if day.is_none() {
+            if let Some(parsed) = leading_digits(token, 1, 2) {
+                day = Some(parsed);
+                continue;
+            }
+        }
+        if month.is_none() {
+            let prefix = token.get(..3).unwrap_or_default().to_ascii_lowercase();
+            const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+            if let Some(index) = MONTHS.iter().position(|name| *name == prefix) {
+                month = Some(index as u64 + 1);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some(parsed) = leading_digits(token, 2, 4) {
+                year = Some(parsed);
+            }
+        }
+    }
+
+    let ((hour, minute, second), day, month, mut year) = (time?, day?, month?, year?);
+    if year < 70 {
+        year += 2000;
+    } else if year < 100 {
+        year += 1900;
+    }
+    if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    let seconds = days.checked_mul(86_400)? + hour * 3600 + minute * 60 + second;
+    // Dates before the epoch have expired all the same.
+    Some(u64::try_from(seconds).map_or(UNIX_EPOCH, |seconds| UNIX_EPOCH + Duration::from_secs(seconds)))
+}
+
+/// `hh:mm:ss`, one or two digits each, optionally followed by non-digits.
+fn parse_time(token: &str) -> Option<(i64, i64, i64)> {
+    let mut fields = token.splitn(3, ':');
+    let hour = leading_digits(fields.next()?, 1, 2)?;
+    let minute = leading_digits(fields.next()?, 1, 2)?;
+    let second = leading_digits(fields.next()?, 1, 2)?;
+    Some((hour, minute, second))
+}
+
+/// Between `min` and `max` digits, optionally followed by non-digits.
+fn leading_digits<T: std::str::FromStr>(token: &str, min: usize, max: usize) -> Option<T> {
+    let digits = token.bytes().take_while(u8::is_ascii_digit).count();
+    if digits < min || digits > max {
+        return None;
+    }
+    token[..digits].parse().ok()
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u64, day: u64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(jar: &CookieJar, url: &str) -> Option<String> {
+        jar.header(&Url::parse(url).unwrap()).map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn parses_dates_in_the_formats_servers_send() {
+        let expected = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(parse_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(expected));
+        assert_eq!(parse_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(expected));
+        assert_eq!(parse_date("Sun Nov  6 08:49:37 1994"), Some(expected));
+        assert_eq!(parse_date("Wed, 09 Jun 2021 10:18:14 GMT"), Some(UNIX_EPOCH + Duration::from_secs(1_623_233_894)));
+        assert_eq!(parse_date("Sun, 32 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_date("tomorrow"), None);
+    }
+
+    #[test]
+    fn matches_domain_path_and_scheme() {
+        let jar = CookieJar::new().with_public_suffix_list("com\norg\n");
+        let origin = Url::parse("https://www.example.com/account/login").unwrap();
+        jar.store(&origin, "session=abc; Secure; HttpOnly; SameSite=Lax");
+        jar.store(&origin, "theme=dark; Domain=.Example.com; Path=/");
+        jar.store(&origin, "pref=1; Path=/account/settings");
+        jar.store(&origin, "gone=1; Max-Age=0");
+        jar.store(&origin, "other=1; Domain=example.org");
+        jar.store(&origin, "tld=1; Domain=com");
+        jar.store(&Url::parse("http://www.example.com/").unwrap(), "insecure=1; Secure");
+
+        assert_eq!(header(&jar, "https://www.example.com/account/x").as_deref(), Some("session=abc; theme=dark"));
+        assert_eq!(header(&jar, "https://www.example.com/account/settings/a").as_deref(), Some("pref=1; session=abc; theme=dark"));
+        assert_eq!(header(&jar, "http://www.example.com/account/x").as_deref(), Some("theme=dark"));
+        assert_eq!(header(&jar, "https://api.example.com/account/x").as_deref(), Some("theme=dark"));
+        assert_eq!(header(&jar, "https://example.org/"), None);
+
+        let session = jar.cookies().into_iter().find(|cookie| cookie.name == "session").unwrap();
+        assert!(session.host_only && session.http_only && session.expires.is_none());
+        assert_eq!(session.same_site, Some(SameSite::Lax));
+        assert_eq!(session.path, "/account");
+
+        jar.store(&origin, "theme=light; Domain=example.com; Path=/; Expires=Thu, 01 Jan 1970 00:00:01 GMT");
+        assert_eq!(header(&jar, "https://api.example.com/"), None);
+    }
+
+    #[test]
+    fn refuses_cookies_for_public_suffixes() {
+        let jar = CookieJar::new().with_public_suffix_list("// comment\nuk\nco.uk\n*.ck\n!www.ck\n");
+        let url = Url::parse("https://shop.example.co.uk/").unwrap();
+        jar.store(&url, "a=1; Domain=co.uk");
+        jar.store(&url, "b=2; Domain=example.co.uk");
+        assert_eq!(header(&jar, "https://example.co.uk/").as_deref(), Some("b=2"));
+
+        // A public suffix may still set a host-only cookie for itself.
+        jar.store(&Url::parse("https://co.uk/").unwrap(), "c=3; Domain=co.uk");
+        assert_eq!(header(&jar, "https://co.uk/").as_deref(), Some("c=3"));
+
+        let suffixes = jar.suffixes.as_ref().unwrap();
+        assert!(suffixes.is_public_suffix("anything.ck"));
+        assert!(!suffixes.is_public_suffix("www.ck"));
+        assert_eq!(suffixes.public_suffix("a.b.example.com"), "com");
+    }
+
+    #[test]
+    fn keeps_parent_domain_cookies_host_only_without_a_list() {
+        let jar = CookieJar::new();
+        let url = Url::parse("https://www.example.co.uk/").unwrap();
+        jar.store(&url, "a=1; Domain=co.uk");
+        jar.store(&url, "b=2; Domain=example.co.uk");
+        jar.store(&url, "c=3; Domain=uk");
+        jar.store(&url, "d=4; Domain=www.example.co.uk");
+        assert_eq!(header(&jar, "https://other.co.uk/"), None);
+        assert_eq!(header(&jar, "https://example.co.uk/"), None);
+        assert_eq!(header(&jar, "https://www.example.co.uk/").as_deref(), Some("a=1; b=2; d=4"));
+        assert_eq!(header(&jar, "https://sub.www.example.co.uk/").as_deref(), Some("d=4"));
+
+        let url = Url::parse("https://www.example.com/").unwrap();
+        jar.store(&url, "e=5; Domain=example.com");
+        assert_eq!(header(&jar, "https://www.example.com/").as_deref(), Some("e=5"));
+        assert_eq!(header(&jar, "https://api.example.com/"), None);
+    }
+
+    #[test]
+    fn domain_cookies_replace_host_only_ones() {
+        let jar = CookieJar::new();
+        let url = Url::parse("https://example.com/").unwrap();
+        jar.store(&url, "id=1");
+        jar.store(&url, "id=2; Domain=example.com");
+        assert_eq!(header(&jar, "https://example.com/").as_deref(), Some("id=2"));
+        let cookies = jar.cookies();
+        assert_eq!(cookies.len(), 1);
+        assert!(!cookies[0].host_only);
+
+        jar.store(&url, "id=3");
+        assert_eq!(header(&jar, "https://example.com/").as_deref(), Some("id=3"));
+        assert_eq!(header(&jar, "https://www.example.com/"), None);
+    }
+
+    #[test]
+    fn round_trips_through_the_netscape_format() {
+        let jar = CookieJar::new().with_public_suffix_list("com\n");
+        let url = Url::parse("https://www.example.com/").unwrap();
+        jar.store(&url, "a=1; Domain=example.com; Max-Age=3600; HttpOnly");
+        jar.store(&url, "b=2; Secure");
+
+        let loaded = CookieJar::new();
+        loaded.load_netscape(&jar.to_netscape()).unwrap();
+        let mut names: Vec<_> = loaded.cookies().into_iter().map(|cookie| (cookie.name, cookie.host_only, cookie.http_only, cookie.secure)).collect();
+        names.sort();
+        assert_eq!(names, [("a".to_string(), false, true, false), ("b".to_string(), true, false, true)]);
+        assert_eq!(header(&loaded, "https://www.example.com/").as_deref(), Some("a=1; b=2"));
+        assert!(loaded.load_netscape("example.com\tTRUE\t/\n").is_err());
+    }
+}
@@ -0,0 +1,271 @@
+//! Transparent decoding of `Content-Encoding`, with one cargo feature per
+//! coding: `gzip`, `deflate`, `brotli` and `zstd`.
+
+use crate::ResponseBody;
+use http::HeaderValue;
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli", feature = "zstd"))]
+use {
+    crate::body::Frame,
+    crate::Error,
+    bytes::BytesMut,
+    futures_util::stream,
+    http::header::{CONTENT_ENCODING, CONTENT_LENGTH},
+    http::HeaderMap,
+    std::io,
+    std::pin::Pin,
+    std::task::{Context, Poll},
+    tokio::io::{AsyncRead, AsyncReadExt, BufReader, ReadBuf},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Coding {
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "deflate")]
+    Deflate,
+    #[cfg(feature = "brotli")]
+    Brotli,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Coding {
+    const ENABLED: &'static [Coding] = &[
+        #[cfg(feature = "gzip")]
+        Coding::Gzip,
+        #[cfg(feature = "deflate")]
+        Coding::Deflate,
+        #[cfg(feature = "brotli")]
+        Coding::Brotli,
+        #[cfg(feature = "zstd")]
+        Coding::Zstd,
+    ];
+
+    fn token(self) -> &'static str {
+        match self {
+            #[cfg(feature = "gzip")]
+            Coding::Gzip => "gzip",
+            #[cfg(feature = "deflate")]
+            Coding::Deflate => "deflate",
+            #[cfg(feature = "brotli")]
+            Coding::Brotli => "br",
+            #[cfg(feature = "zstd")]
+            Coding::Zstd => "zstd",
+        }
+    }
+
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli", feature = "zstd"))]
+    fn parse(value: &str) -> Option<Coding> {
+        let value = value.trim();
+        #[cfg(feature = "gzip")]
+        if value.eq_ignore_ascii_case("x-gzip") {
+            return Some(Coding::Gzip);
+        }
+        Coding::ENABLED.iter().copied().find(|coding| value.eq_ignore_ascii_case(coding.token()))
+    }
+}
+
+/// The `Accept-Encoding` value listing every coding compiled in, if any.
+pub(crate) fn accept_encoding() -> Option<HeaderValue> {
+    if Coding::ENABLED.is_empty() {
+        return None;
+    }
+    let tokens: Vec<&str> = Coding::ENABLED.iter().map(|coding| coding.token()).collect();
+    Some(HeaderValue::from_str(&tokens.join(", ")).expect("coding tokens are valid header values"))
+}
+
+/// Puts a decoder in front of `body` if `headers` name a single coding this
+/// build supports. `Content-Encoding` and `Content-Length` describe the
+/// encoded bytes, so both are removed when the body is decoded. The decoded
+/// output fails with `Error::BodyTooLarge` once it grows past `limit`.
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli", feature = "zstd"))]
+pub(crate) fn decode(headers: &mut HeaderMap, body: ResponseBody, limit: Option<u64>) -> ResponseBody {
+    let mut values = headers.get_all(CONTENT_ENCODING).iter();
+    let (Some(value), None) = (values.next(), values.next()) else { return body };
+    let Some(coding) = value.to_str().ok().and_then(Coding::parse) else { return body };
+    headers.remove(CONTENT_ENCODING);
+    headers.remove(CONTENT_LENGTH);
+    ResponseBody::from_frames(stream::try_unfold(State::Start(body, coding, limit), next_frame))
+}
+
+#[cfg(not(any(feature = "gzip", feature = "deflate", feature = "brotli", feature = "zstd")))]
+pub(crate) fn decode(_headers: &mut http::HeaderMap, body: ResponseBody, _limit: Option<u64>) -> ResponseBody {
+    body
+}
+
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli", feature = "zstd"))]
+enum State {
+    Start(ResponseBody, Coding, Option<u64>),
+    /// The decoder, the size limit and how many bytes it has produced.
+    Decoding(Decoder, Option<u64>, u64),
+    Done,
+}
+
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli", feature = "zstd"))]
+async fn next_frame(state: State) -> Result<Option<(Frame, State)>, Error> {
+    let (mut decoder, limit, decoded) = match state {
+        // No decoder accepts empty input, yet servers do label empty bodies.
+        State::Start(mut body, coding, limit) => match body.chunk().await? {
+            Some(chunk) => {
+                body.push_front(chunk);
+                (Decoder::new(coding, body), limit, 0)
+            }
+            None => return finish(body).await,
+        },
+        State::Decoding(decoder, limit, decoded) => (decoder, limit, decoded),
+        State::Done => return Ok(None),
+    };
+    let mut buf = BytesMut::with_capacity(8 * 1024);
+    match decoder.read_buf(&mut buf).await {
+        Ok(0) => finish(decoder.into_inner()).await,
+        Ok(n) => {
+            let decoded = decoded + n as u64;
+            if let Some(limit) = limit.filter(|&limit| decoded > limit) {
+                return Err(Error::BodyTooLarge { limit });
+            }
+            Ok(Some((Frame::Data(buf.freeze()), State::Decoding(decoder, limit, decoded))))
+        }
+        Err(err) => Err(into_error(err)),
+    }
+}
+
+/// Reads the raw body to its end, so the connection can be reused and any
+/// trailers arrive, and hands the trailers on.
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli", feature = "zstd"))]
+async fn finish(mut body: ResponseBody) -> Result<Option<(Frame, State)>, Error> {
+    while body.chunk().await?.is_some() {}
+    Ok(body.trailers().cloned().map(|trailers| (Frame::Trailers(trailers), State::Done)))
+}
+
+/// Errors from the raw body pass through the decoder wrapped in `io::Error`;
+/// anything else is the decoder rejecting the data.
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli", feature = "zstd"))]
+fn into_error(err: io::Error) -> Error {
+    if !err.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+        return Error::Decode(err);
+    }
+    match err.into_inner().map(|inner| inner.downcast::<Error>()) {
+        Some(Ok(err)) => *err,
+        _ => Error::Decode(io::Error::other("decoder failed")),
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli", feature = "zstd"))]
+enum Decoder {
+    #[cfg(feature = "gzip")]
+    Gzip(async_compression::tokio::bufread::GzipDecoder<BufReader<ResponseBody>>),
+    #[cfg(feature = "deflate")]
+    Deflate(async_compression::tokio::bufread::ZlibDecoder<BufReader<ResponseBody>>),
+    #[cfg(feature = "brotli")]
+    Brotli(async_compression::tokio::bufread::BrotliDecoder<BufReader<ResponseBody>>),
+    #[cfg(feature = "zstd")]
+    Zstd(async_compression::tokio::bufread::ZstdDecoder<BufReader<ResponseBody>>),
+}
+
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli", feature = "zstd"))]
+impl Decoder {
+    fn new(coding: Coding, body: ResponseBody) -> Self {
+        let reader = BufReader::new(body);
+        match coding {
+            #[cfg(feature = "gzip")]
+            Coding::Gzip => {
+                let mut decoder = async_compression::tokio::bufread::GzipDecoder::new(reader);
+                decoder.multiple_members(true);
+                Decoder::Gzip(decoder)
+            }
+            // HTTP's "deflate" is the zlib format, not a raw deflate stream.
+            #[cfg(feature = "deflate")]
+            Coding::Deflate => Decoder::Deflate(async_compression::tokio::bufread::ZlibDecoder::new(reader)),
+            #[cfg(feature = "brotli")]
+            Coding::Brotli => Decoder::Brotli(async_compression::tokio::bufread::BrotliDecoder::new(reader)),
+            #[cfg(feature = "zstd")]
+            Coding::Zstd => Decoder::Zstd(async_compression::tokio::bufread::ZstdDecoder::new(reader)),
+        }
+    }
+
+    fn into_inner(self) -> ResponseBody {
+        match self {
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(decoder) => decoder.into_inner().into_inner(),
+            #[cfg(feature = "deflate")]
+            Decoder::Deflate(decoder) => decoder.into_inner().into_inner(),
+            #[cfg(feature = "brotli")]
+            Decoder::Brotli(decoder) => decoder.into_inner().into_inner(),
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(decoder) => decoder.into_inner().into_inner(),
+        }
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli", feature = "zstd"))]
+impl AsyncRead for Decoder {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(decoder) => Pin::new(decoder).poll_read(cx, buf),
+            #[cfg(feature = "deflate")]
+            Decoder::Deflate(decoder) => Pin::new(decoder).poll_read(cx, buf),
+            #[cfg(feature = "brotli")]
+            Decoder::Brotli(decoder) => Pin::new(decoder).poll_read(cx, buf),
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(decoder) => Pin::new(decoder).poll_read(cx, buf),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod tests {
+    use super::*;
+    use async_compression::tokio::write::GzipEncoder;
+    use bytes::Bytes;
+    use tokio::io::AsyncWriteExt;
+
+    async fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzipEncoder::new(Vec::new());
+        encoder.write_all(data).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        encoder.into_inner()
+    }
+
+    #[tokio::test]
+    async fn decodes_streamed_body_and_keeps_trailers() {
+        let text = "kusari ".repeat(10_000);
+        let encoded = gzip(text.as_bytes()).await;
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-checksum", HeaderValue::from_static("abc"));
+        let frames: Vec<Result<Frame, Error>> = encoded
+            .chunks(100)
+            .map(|chunk| Ok(Frame::Data(Bytes::copy_from_slice(chunk))))
+            .chain([Ok(Frame::Trailers(trailers))])
+            .collect();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("GZIP"));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(encoded.len()));
+        let mut body = decode(&mut headers, ResponseBody::from_frames(stream::iter(frames)), None);
+        assert!(headers.is_empty());
+
+        let mut decoded = Vec::new();
+        while let Some(chunk) = body.chunk().await.unwrap() {
+            decoded.extend_from_slice(&chunk);
+        }
+        assert_eq!(decoded, text.as_bytes());
+        assert_eq!(body.trailers().unwrap()["x-checksum"], "abc");
+    }
+
+    #[tokio::test]
+    async fn leaves_unknown_codings_and_reports_corrupt_data() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("compress"));
+        let body = decode(&mut headers, ResponseBody::from(b"raw".to_vec()), None);
+        assert_eq!(body.as_bytes(), Some(&b"raw"[..]));
+        assert!(headers.contains_key(CONTENT_ENCODING));
+
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        let body = decode(&mut headers, ResponseBody::from(b"not gzip".to_vec()), None);
+        assert!(matches!(body.bytes().await, Err(Error::Decode(_))));
+
+        let body = decode(&mut headers, ResponseBody::empty(), None);
+        assert!(body.bytes().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,452 @@
+//! HPACK header compression for HTTP/2 (RFC 7541).
+//!
+//! The decoder implements the full format, including the dynamic table and
+//! Huffman-coded strings. The encoder keeps no dynamic table: it indexes
+//! static-table matches and sends everything else as plain literals, which
+//! lets the peer use any table size it likes.
+
+use crate::http2::Reason;
+use bytes::{BufMut, Bytes, BytesMut};
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Huffman code and bit length for every byte value, plus EOS at index 256.
+const HUFFMAN: [(u32, u8); 257] = [
+    (0x1ff8, 13), (0x7fffd8, 23), (0xfffffe2, 28), (0xfffffe3, 28), (0xfffffe4, 28), (0xfffffe5, 28),
+    (0xfffffe6, 28), (0xfffffe7, 28), (0xfffffe8, 28), (0xffffea, 24), (0x3ffffffc, 30), (0xfffffe9, 28),
+    (0xfffffea, 28), (0x3ffffffd, 30), (0xfffffeb, 28), (0xfffffec, 28), (0xfffffed, 28), (0xfffffee, 28),
+    (0xfffffef, 28), (0xffffff0, 28), (0xffffff1, 28), (0xffffff2, 28), (0x3ffffffe, 30), (0xffffff3, 28),
+    (0xffffff4, 28), (0xffffff5, 28), (0xffffff6, 28), (0xffffff7, 28), (0xffffff8, 28), (0xffffff9, 28),
+    (0xffffffa, 28), (0xffffffb, 28), (0x14, 6), (0x3f8, 10), (0x3f9, 10), (0xffa, 12),
+    (0x1ff9, 13), (0x15, 6), (0xf8, 8), (0x7fa, 11), (0x3fa, 10), (0x3fb, 10),
+    (0xf9, 8), (0x7fb, 11), (0xfa, 8), (0x16, 6), (0x17, 6), (0x18, 6),
+    (0x0, 5), (0x1, 5), (0x2, 5), (0x19, 6), (0x1a, 6), (0x1b, 6),
+    (0x1c, 6), (0x1d, 6), (0x1e, 6), (0x1f, 6), (0x5c, 7), (0xfb, 8),
+    (0x7ffc, 15), (0x20, 6), (0xffb, 12), (0x3fc, 10), (0x1ffa, 13), (0x21, 6),
+    (0x5d, 7), (0x5e, 7), (0x5f, 7), (0x60, 7), (0x61, 7), (0x62, 7),
+    (0x63, 7), (0x64, 7), (0x65, 7), (0x66, 7), (0x67, 7), (0x68, 7),
+    (0x69, 7), (0x6a, 7), (0x6b, 7), (0x6c, 7), (0x6d, 7), (0x6e, 7),
+    (0x6f, 7), (0x70, 7), (0x71, 7), (0x72, 7), (0xfc, 8), (0x73, 7),
+    (0xfd, 8), (0x1ffb, 13), (0x7fff0, 19), (0x1ffc, 13), (0x3ffc, 14), (0x22, 6),
+    (0x7ffd, 15), (0x3, 5), (0x23, 6), (0x4, 5), (0x24, 6), (0x5, 5),
+    (0x25, 6), (0x26, 6), (0x27, 6), (0x6, 5), (0x74, 7), (0x75, 7),
+    (0x28, 6), (0x29, 6), (0x2a, 6), (0x7, 5), (0x2b, 6), (0x76, 7),
+    (0x2c, 6), (0x8, 5), (0x9, 5), (0x2d, 6), (0x77, 7), (0x78, 7),
+    (0x79, 7), (0x7a, 7), (0x7b, 7), (0x7ffe, 15), (0x7fc, 11), (0x3ffd, 14),
+    (0x1ffd, 13), (0xffffffc, 28), (0xfffe6, 20), (0x3fffd2, 22), (0xfffe7, 20), (0xfffe8, 20),
+    (0x3fffd3, 22), (0x3fffd4, 22), (0x3fffd5, 22), (0x7fffd9, 23), (0x3fffd6, 22), (0x7fffda, 23),
+    (0x7fffdb, 23), (0x7fffdc, 23), (0x7fffdd, 23), (0x7fffde, 23), (0xffffeb, 24), (0x7fffdf, 23),
+    (0xffffec, 24), (0xffffed, 24), (0x3fffd7, 22), (0x7fffe0, 23), (0xffffee, 24), (0x7fffe1, 23),
+    (0x7fffe2, 23), (0x7fffe3, 23), (0x7fffe4, 23), (0x1fffdc, 21), (0x3fffd8, 22), (0x7fffe5, 23),
+    (0x3fffd9, 22), (0x7fffe6, 23), (0x7fffe7, 23), (0xffffef, 24), (0x3fffda, 22), (0x1fffdd, 21),
+    (0xfffe9, 20), (0x3fffdb, 22), (0x3fffdc, 22), (0x7fffe8, 23), (0x7fffe9, 23), (0x1fffde, 21),
+    (0x7fffea, 23), (0x3fffdd, 22), (0x3fffde, 22), (0xfffff0, 24), (0x1fffdf, 21), (0x3fffdf, 22),
+    (0x7fffeb, 23), (0x7fffec, 23), (0x1fffe0, 21), (0x1fffe1, 21), (0x3fffe0, 22), (0x1fffe2, 21),
+    (0x7fffed, 23), (0x3fffe1, 22), (0x7fffee, 23), (0x7fffef, 23), (0xfffea, 20), (0x3fffe2, 22),
+    (0x3fffe3, 22), (0x3fffe4, 22), (0x7ffff0, 23), (0x3fffe5, 22), (0x3fffe6, 22), (0x7ffff1, 23),
+    (0x3ffffe0, 26), (0x3ffffe1, 26), (0xfffeb, 20), (0x7fff1, 19), (0x3fffe7, 22), (0x7ffff2, 23),
+    (0x3fffe8, 22), (0x1ffffec, 25), (0x3ffffe2, 26), (0x3ffffe3, 26), (0x3ffffe4, 26), (0x7ffffde, 27),
+    (0x7ffffdf, 27), (0x3ffffe5, 26), (0xfffff1, 24), (0x1ffffed, 25), (0x7fff2, 19), (0x1fffe3, 21),
+    (0x3ffffe6, 26), (0x7ffffe0, 27), (0x7ffffe1, 27), (0x3ffffe7, 26), (0x7ffffe2, 27), (0xfffff2, 24),
+    (0x1fffe4, 21), (0x1fffe5, 21), (0x3ffffe8, 26), (0x3ffffe9, 26), (0xffffffd, 28), (0x7ffffe3, 27),
+    (0x7ffffe4, 27), (0x7ffffe5, 27), (0xfffec, 20), (0xfffff3, 24), (0xfffed, 20), (0x1fffe6, 21),
+    (0x3fffe9, 22), (0x1fffe7, 21), (0x1fffe8, 21), (0x7ffff3, 23), (0x3fffea, 22), (0x3fffeb, 22),
+    (0x1ffffee, 25), (0x1ffffef, 25), (0xfffff4, 24), (0xfffff5, 24), (0x3ffffea, 26), (0x7ffff4, 23),
+    (0x3ffffeb, 26), (0x7ffffe6, 27), (0x3ffffec, 26), (0x3ffffed, 26), (0x7ffffe7, 27), (0x7ffffe8, 27),
+    (0x7ffffe9, 27), (0x7ffffea, 27), (0x7ffffeb, 27), (0xffffffe, 28), (0x7ffffec, 27), (0x7ffffed, 27),
+    (0x7ffffee, 27), (0x7ffffef, 27), (0x7fffff0, 27), (0x3ffffee, 26), (0x3fffffff, 30),
+];
+
+/// Every entry costs its name and value plus 32 bytes of bookkeeping.
+fn entry_size(name: &[u8], value: &[u8]) -> usize {
+    name.len() + value.len() + 32
+}
+
+pub(crate) struct Decoder {
+    table: VecDeque<(Bytes, Bytes)>,
+    size: usize,
+    max_size: usize,
+    /// The table size we advertised; the peer may shrink below it but never exceed it.
+    limit: usize,
+}
+
+impl Decoder {
+    pub fn new(limit: usize) -> Self {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: limit,
+            limit,
+        }
+    }
+
+    /// Decodes a complete header block into name/value pairs in wire order.
+    ///
+    /// `max_list_size` bounds the decoded size, measured as RFC 9113 does for
+    /// `SETTINGS_MAX_HEADER_LIST_SIZE`.
+    pub fn decode(&mut self, mut block: &[u8], max_list_size: usize) -> Result<Vec<(Bytes, Bytes)>, Reason> {
+        let mut fields = Vec::new();
+        let mut list_size = 0;
+        while let Some(&first) = block.first() {
+            let (name, value) = if first & 0x80 != 0 {
+                let index = decode_int(&mut block, 7)?;
+                self.get(index)?
+            } else if first & 0xc0 == 0x40 {
+                let (name, value) = self.decode_literal(&mut block, 6)?;
+                self.insert(name.clone(), value.clone());
+                (name, value)
+            } else if first & 0xe0 == 0x20 {
+                if !fields.is_empty() {
+                    return Err(Reason::COMPRESSION_ERROR);
+                }
+                let size = decode_int(&mut block, 5)?;
+                if size > self.limit {
+                    return Err(Reason::COMPRESSION_ERROR);
+                }
+                self.max_size = size;
+                self.evict(0);
+                continue;
+            } else {
+                // Literal without indexing (0000) or never indexed (0001).
+                self.decode_literal(&mut block, 4)?
+            };
+            list_size += entry_size(&name, &value);
+            if list_size > max_list_size {
+                return Err(Reason::PROTOCOL_ERROR);
+            }
+            fields.push((name, value));
+        }
+        Ok(fields)
+    }
+
+    fn decode_literal(&self, block: &mut &[u8], prefix: u8) -> Result<(Bytes, Bytes), Reason> {
+        let name = match decode_int(block, prefix)? {
+            0 => decode_string(block)?,
+            index => self.get(index)?.0,
+        };
+        Ok((name, decode_string(block)?))
+    }
+
+    fn get(&self, index: usize) -> Result<(Bytes, Bytes), Reason> {
+        match index {
+            0 => Err(Reason::COMPRESSION_ERROR),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((Bytes::from_static(name.as_bytes()), Bytes::from_static(value.as_bytes())))
+            }
+            _ => self.table.get(index - 62).cloned().ok_or(Reason::COMPRESSION_ERROR),
+        }
+    }
+
+    fn insert(&mut self, name: Bytes, value: Bytes) {
+        let size = entry_size(&name, &value);
+        self.evict(size);
+        // An entry larger than the whole table empties it and is not stored.
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front((name, value));
+        }
+    }
+
+    /// Drops the oldest entries until `incoming` more bytes fit.
+    fn evict(&mut self, incoming: usize) {
+        while self.size + incoming > self.max_size {
+            let Some((name, value)) = self.table.pop_back() else { break };
+            self.size -= entry_size(&name, &value);
+        }
+    }
+}
+
+/// Appends one field to a header block.
+///
+/// Sensitive values are sent as never-indexed literals so intermediaries
+/// will not put them in their own tables either.
+pub(crate) fn encode_field(out: &mut BytesMut, name: &[u8], value: &[u8], sensitive: bool) {
+    let mut name_index = 0;
+    for (i, (static_name, static_value)) in STATIC_TABLE.iter().enumerate() {
+        if static_name.as_bytes() != name {
+            continue;
+        }
+        if !sensitive && static_value.as_bytes() == value {
+            encode_int(out, i + 1, 7, 0x80);
+            return;
+        }
+        if name_index == 0 {
+            name_index = i + 1;
+        }
+    }
+
+    encode_int(out, name_index, 4, if sensitive { 0x10 } else { 0x00 });
+    if name_index == 0 {
+        encode_string(out, name);
+    }
+    encode_string(out, value);
+}
+
+fn encode_int(out: &mut BytesMut, mut value: usize, prefix: u8, flags: u8) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        out.put_u8(flags | value as u8);
+        return;
+    }
+    out.put_u8(flags | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        out.put_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.put_u8(value as u8);
+}
+
+fn encode_string(out: &mut BytesMut, value: &[u8]) {
+    encode_int(out, value.len(), 7, 0x00);
+    out.extend_from_slice(value);
+}
+
+fn decode_int(block: &mut &[u8], prefix: u8) -> Result<usize, Reason> {
+    let max = (1usize << prefix) - 1;
+    let (&first, rest) = block.split_first().ok_or(Reason::COMPRESSION_ERROR)?;
+    *block = rest;
+    let mut value = first as usize & max;
+    if value < max {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = block.split_first().ok_or(Reason::COMPRESSION_ERROR)?;
+        *block = rest;
+        // Anything past 28 bits is far beyond any sane length or index.
+        if shift > 21 {
+            return Err(Reason::COMPRESSION_ERROR);
+        }
+        value += ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn decode_string(block: &mut &[u8]) -> Result<Bytes, Reason> {
+    let huffman = block.first().is_some_and(|b| b & 0x80 != 0);
+    let len = decode_int(block, 7)?;
+    if block.len() < len {
+        return Err(Reason::COMPRESSION_ERROR);
+    }
+    let (raw, rest) = block.split_at(len);
+    *block = rest;
+    if huffman {
+        huffman_decode(raw).map(Bytes::from)
+    } else {
+        Ok(Bytes::copy_from_slice(raw))
+    }
+}
+
+/// A binary tree over the Huffman codes. Each node holds its two children;
+/// values of 512 and above are leaves carrying the symbol `value - 512`.
+fn huffman_tree() -> &'static [[u16; 2]] {
+    static TREE: OnceLock<Vec<[u16; 2]>> = OnceLock::new();
+    TREE.get_or_init(|| {
+        let mut tree = vec![[0u16; 2]];
+        for (symbol, &(code, bits)) in HUFFMAN.iter().enumerate() {
+            let mut node = 0;
+            for i in (0..bits).rev() {
+                let bit = (code >> i) as usize & 1;
+                if i == 0 {
+                    tree[node][bit] = 512 + symbol as u16;
+                } else {
+                    if tree[node][bit] == 0 {
+                        tree.push([0; 2]);
+                        tree[node][bit] = (tree.len() - 1) as u16;
+                    }
+                    node = tree[node][bit] as usize;
+                }
+            }
+        }
+        tree
+    })
+}
+
+fn huffman_decode(input: &[u8]) -> Result<Vec<u8>, Reason> {
+    let tree = huffman_tree();
+    let mut out = Vec::with_capacity(input.len() * 8 / 5);
+    let mut node = 0;
+    // Bits consumed since the last complete symbol, and whether all were ones.
+    let mut pending = 0;
+    let mut all_ones = true;
+    for byte in input {
+        for i in (0..8).rev() {
+            let bit = (byte >> i) & 1;
+            pending += 1;
+            all_ones &= bit == 1;
+            match tree[node][bit as usize] {
+                512..=767 => {
+                    out.push((tree[node][bit as usize] - 512) as u8);
+                    node = 0;
+                    pending = 0;
+                    all_ones = true;
+                }
+                768 => return Err(Reason::COMPRESSION_ERROR),
+                next => node = next as usize,
+            }
+        }
+    }
+    // Padding must be a strict prefix of EOS: at most 7 one bits.
+    if pending > 7 || !all_ones {
+        return Err(Reason::COMPRESSION_ERROR);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn pairs(fields: &[(Bytes, Bytes)]) -> Vec<(&str, &str)> {
+        fields
+            .iter()
+            .map(|(n, v)| (std::str::from_utf8(n).unwrap(), std::str::from_utf8(v).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_rfc_huffman_response_examples() {
+        // RFC 7541 appendix C.6, which also exercises eviction from a 256-byte table.
+        let mut decoder = Decoder::new(256);
+        let first = decoder
+            .decode(
+                &hex("4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 82a6
+                      2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3"),
+                usize::MAX,
+            )
+            .unwrap();
+        assert_eq!(
+            pairs(&first),
+            [
+                (":status", "302"),
+                ("cache-control", "private"),
+                ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+                ("location", "https://www.example.com"),
+            ]
+        );
+
+        let second = decoder.decode(&hex("4883 640e ffc1 c0bf"), usize::MAX).unwrap();
+        assert_eq!(second[0].1, "307");
+        assert_eq!(second[3].1, "https://www.example.com");
+
+        let third = decoder
+            .decode(
+                &hex("88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b d9ab
+                      77ad 94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f
+                      9587 3160 65c0 03ed 4ee5 b106 3d50 07"),
+                usize::MAX,
+            )
+            .unwrap();
+        assert_eq!(
+            pairs(&third),
+            [
+                (":status", "200"),
+                ("cache-control", "private"),
+                ("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+                ("location", "https://www.example.com"),
+                ("content-encoding", "gzip"),
+                ("set-cookie", "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1"),
+            ]
+        );
+        assert_eq!(decoder.size, 215);
+    }
+
+    #[test]
+    fn encoded_fields_round_trip() {
+        let mut out = BytesMut::new();
+        encode_field(&mut out, b":method", b"GET", false);
+        encode_field(&mut out, b":path", b"/search?q=x", false);
+        encode_field(&mut out, b"authorization", b"Bearer t", true);
+        encode_field(&mut out, b"x-custom", &[b'a'; 200], false);
+        assert_eq!(out[0], 0x82);
+
+        let fields = Decoder::new(4096).decode(&out, usize::MAX).unwrap();
+        assert_eq!(fields[1], (Bytes::from(":path"), Bytes::from("/search?q=x")));
+        assert_eq!(fields[2].1, "Bearer t");
+        assert_eq!(fields[3].1.len(), 200);
+    }
+
+    #[test]
+    fn rejects_bad_padding_and_oversized_lists() {
+        // "a" is 00011; padding with zeros instead of ones is invalid.
+        assert_eq!(huffman_decode(&[0b0001_1111]).unwrap(), b"a");
+        assert!(huffman_decode(&[0b0001_1000]).is_err());
+        let mut out = BytesMut::new();
+        encode_field(&mut out, b"x-a", b"b", false);
+        assert_eq!(Decoder::new(4096).decode(&out, 10), Err(Reason::PROTOCOL_ERROR));
+    }
+}
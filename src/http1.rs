@@ -0,0 +1,697 @@
+use crate::chunked::ChunkedDecoder;
+use crate::{Error, InformationalResponse, Request};
+use bytes::{Bytes, BytesMut};
+use http::header::{CONTENT_LENGTH, EXPECT, HOST, TRAILER, TRANSFER_ENCODING};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Version};
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use url::Url;
+
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+
+/// The spelling and order in which a caller added header names.
+///
+/// `HeaderMap` lowercases names and groups repeated ones together, so this log
+/// is kept next to it for servers that are picky about the exact wire bytes.
+/// Headers present in the map but missing from the log are written after the
+/// logged ones, in map order, with lowercase names.
+#[derive(Clone, Debug, Default)]
+pub struct OriginalHeaders {
+    names: Vec<(HeaderName, String)>,
+}
+
+impl OriginalHeaders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records one occurrence of `name` as spelled, returning the parsed name.
+    pub fn record(&mut self, name: &str) -> Result<HeaderName, Error> {
+        let parsed = HeaderName::from_str(name).map_err(|_| Error::InvalidHeader(name.to_string()))?;
+        self.names.push((parsed.clone(), name.to_string()));
+        Ok(parsed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// The four shapes a request-target can take on an HTTP/1.1 request line
+/// (RFC 9112 section 3.2).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TargetForm {
+    /// `/path?query`, used for requests sent straight to the origin.
+    Origin,
+    /// `http://host/path?query`, used for requests sent to a forward proxy.
+    Absolute,
+    /// `host:port`, used by `CONNECT`.
+    Authority,
+    /// `*`, used by server-wide `OPTIONS` requests.
+    Asterisk,
+}
+
+impl TargetForm {
+    /// `OPTIONS` requests for the path `/*` ask about the server as a whole
+    /// and are sent with the asterisk-form.
+    pub fn select(method: &Method, url: &Url, via_proxy: bool) -> TargetForm {
+        if method == Method::CONNECT {
+            TargetForm::Authority
+        } else if method == Method::OPTIONS && url.path() == "/*" && url.query().is_none() {
+            TargetForm::Asterisk
+        } else if via_proxy {
+            TargetForm::Absolute
+        } else {
+            TargetForm::Origin
+        }
+    }
+}
+
+pub fn request_target(url: &Url, form: TargetForm) -> String {
+    match form {
+        TargetForm::Origin => {
+            let mut target = url.path().to_string();
+            if target.is_empty() {
+                target.push('/');
+            }
+            if let Some(query) = url.query() {
+                target.push('?');
+                target.push_str(query);
+            }
+            target
+        }
+        TargetForm::Absolute => {
+            let mut url = url.clone();
+            url.set_fragment(None);
+            let _ = url.set_username("");
+            let _ = url.set_password(None);
+            url.to_string()
+        }
+        TargetForm::Authority => authority(url, true),
+        TargetForm::Asterisk => "*".to_string(),
+    }
+}
+
+/// `host[:port]` for the `Host` header; the port is left out when it is the
+/// scheme's default unless `always_port` is set.
+pub fn authority(url: &Url, always_port: bool) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port_or_known_default() {
+        Some(port) if always_port || url.port().is_some() => format!("{}:{}", host, port),
+        _ => host.to_string(),
+    }
+}
+
+/// Writes `name: value\r\n` with the value bytes untouched, refusing values
+/// that would break out of the header line.
+pub fn write_header(out: &mut Vec<u8>, name: &str, value: &HeaderValue) -> Result<(), Error> {
+    if value.as_bytes().iter().any(|&b| b == b'\r' || b == b'\n' || b == 0) {
+        return Err(Error::InvalidHeader(name.to_string()));
+    }
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(b": ");
+    out.extend_from_slice(value.as_bytes());
+    out.extend_from_slice(b"\r\n");
+    Ok(())
+}
+
+/// Writes every header in `headers`, following the order and spelling logged
+/// in `original` where there is one.
+pub fn write_headers(out: &mut Vec<u8>, headers: &HeaderMap, original: &OriginalHeaders) -> Result<(), Error> {
+    let mut written: HashMap<&HeaderName, usize> = HashMap::new();
+    for (name, spelled) in &original.names {
+        let count = written.entry(name).or_default();
+        if let Some(value) = headers.get_all(name).iter().nth(*count) {
+            write_header(out, spelled, value)?;
+            *count += 1;
+        }
+    }
+    for name in headers.keys() {
+        let skip = written.get(name).copied().unwrap_or_default();
+        for value in headers.get_all(name).iter().skip(skip) {
+            write_header(out, name.as_str(), value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Serializes the request line and header block, adding `Host` and the
+/// body framing headers unless the caller already supplied them.
+pub fn encode_request_head(request: &Request, url: &Url, form: TargetForm) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::with_capacity(256);
+    out.extend_from_slice(format!("{} {} HTTP/1.1\r\n", request.method, request_target(url, form)).as_bytes());
+
+    if !request.headers.contains_key(HOST) {
+        let host = authority(url, form == TargetForm::Authority);
+        let host = HeaderValue::from_str(&host).map_err(|_| Error::InvalidUrl(url.to_string()))?;
+        write_header(&mut out, "Host", &host)?;
+    }
+
+    write_headers(&mut out, &request.headers, &request.original_headers)?;
+
+    let body = &request.body;
+    if body.is_chunked() {
+        if !request.headers.contains_key(TRANSFER_ENCODING) {
+            write_header(&mut out, "Transfer-Encoding", &HeaderValue::from_static("chunked"))?;
+        }
+        if let Some(trailers) = body.trailers().filter(|t| !t.is_empty() && !request.headers.contains_key(TRAILER)) {
+            let names: Vec<&str> = trailers.keys().map(|name| name.as_str()).collect();
+            write_header(&mut out, "Trailer", &HeaderValue::from_str(&names.join(", ")).expect("header names are valid values"))?;
+        }
+    } else if !request.headers.contains_key(CONTENT_LENGTH) && (!body.is_empty() || expects_body(request)) {
+        write_header(&mut out, "Content-Length", &HeaderValue::from(body.len().unwrap_or_default()))?;
+    }
+
+    out.extend_from_slice(b"\r\n");
+    Ok(out)
+}
+
+/// Whether an empty body still needs `Content-Length: 0`: RFC 9110 section
+/// 8.6 asks for one on methods that define a meaning for content, and some
+/// origins answer 411 without it.
+fn expects_body(request: &Request) -> bool {
+    matches!(request.method, Method::POST | Method::PUT | Method::PATCH) && !request.headers.contains_key(TRANSFER_ENCODING)
+}
+
+/// A parsed status line and header section.
+pub struct ResponseHead {
+    pub version: Version,
+    pub status: StatusCode,
+    pub reason: String,
+    pub headers: HeaderMap,
+    pub raw_headers: Vec<(String, HeaderValue)>,
+    /// 1xx responses that arrived before this one.
+    pub informational: Vec<InformationalResponse>,
+}
+
+/// Parses everything up to the empty line ending the response head.
+///
+/// Lines may end in CRLF or a bare LF, and obsolete line folding is replaced
+/// with a single space as RFC 9112 section 5.2 allows.
+pub fn parse_response_head(head: &[u8]) -> Result<ResponseHead, Error> {
+    let mut lines = head.split(|&b| b == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    let (version, status, reason) = parse_status_line(lines.next().unwrap_or_default())?;
+
+    let mut fields: Vec<(&[u8], Vec<u8>)> = Vec::new();
+    for line in lines.take_while(|line| !line.is_empty()) {
+        if line[0] == b' ' || line[0] == b'\t' {
+            let (_, value) = fields.last_mut().ok_or_else(|| Error::InvalidHeader(String::from_utf8_lossy(line).into_owned()))?;
+            value.push(b' ');
+            value.extend_from_slice(line.trim_ascii());
+        } else {
+            let (name, value) = split_field_line(line)?;
+            fields.push((name, value.to_vec()));
+        }
+    }
+
+    let mut headers = HeaderMap::with_capacity(fields.len());
+    let mut raw_headers = Vec::with_capacity(fields.len());
+    for (name, value) in fields {
+        let invalid = || Error::InvalidHeader(String::from_utf8_lossy(name).into_owned());
+        let parsed = HeaderName::from_bytes(name).map_err(|_| invalid())?;
+        let value = HeaderValue::from_bytes(&value).map_err(|_| invalid())?;
+        headers.append(parsed, value.clone());
+        raw_headers.push((String::from_utf8_lossy(name).into_owned(), value));
+    }
+
+    Ok(ResponseHead {
+        version,
+        status,
+        reason,
+        headers,
+        raw_headers,
+        informational: Vec::new(),
+    })
+}
+
+/// Parses a single `name: value` line, as found in a trailer section.
+pub fn parse_field_line(line: &[u8]) -> Result<(HeaderName, HeaderValue), Error> {
+    let (name, value) = split_field_line(line)?;
+    let invalid = || Error::InvalidHeader(String::from_utf8_lossy(line).into_owned());
+    Ok((
+        HeaderName::from_bytes(name).map_err(|_| invalid())?,
+        HeaderValue::from_bytes(value).map_err(|_| invalid())?,
+    ))
+}
+
+/// Splits a field line at its colon; whitespace after the colon is optional.
+fn split_field_line(line: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    let colon = line
+        .iter()
+        .position(|&b| b == b':')
+        .ok_or_else(|| Error::InvalidHeader(String::from_utf8_lossy(line).into_owned()))?;
+    Ok((&line[..colon], line[colon + 1..].trim_ascii()))
+}
+
+/// Reads up to the empty line ending the final response head, returning the
+/// parsed head and any body bytes that arrived with it.
+///
+/// Interim 1xx responses are collected on the final head. 101 Switching
+/// Protocols is final: whatever follows it is no longer HTTP/1.1.
+pub async fn read_head<S>(io: &mut S) -> Result<(ResponseHead, BytesMut), Error>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = BytesMut::with_capacity(READ_CHUNK_SIZE);
+    let mut informational = Vec::new();
+    let head = read_head_into(io, &mut buf, &mut informational, false).await?;
+    Ok((head.expect("only stops at 100 Continue when asked to"), buf))
+}
+
+/// The length of the head in `buf` up to and including the empty line that
+/// ends it, which may be terminated by CRLF or a bare LF like the other lines.
+fn head_end(buf: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    while let Some(newline) = buf[start..].iter().position(|&b| b == b'\n') {
+        start += newline + 1;
+        match &buf[start..] {
+            [b'\n', ..] => return Some(start + 1),
+            [b'\r', b'\n', ..] => return Some(start + 2),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Like `read_head`, but keeps its state in `buf` and `informational` so
+/// that it can be cancelled and called again without losing data. With
+/// `until_continue` it returns `None` as soon as 100 Continue arrives.
+pub async fn read_head_into<S>(
+    io: &mut S,
+    buf: &mut BytesMut,
+    informational: &mut Vec<InformationalResponse>,
+    until_continue: bool,
+) -> Result<Option<ResponseHead>, Error>
+where
+    S: AsyncRead + Unpin,
+{
+    loop {
+        if let Some(end) = head_end(buf) {
+            let mut head = parse_response_head(&buf.split_to(end))?;
+            if head.status.is_informational() && head.status != StatusCode::SWITCHING_PROTOCOLS {
+                let status = head.status;
+                informational.push(InformationalResponse {
+                    status,
+                    headers: head.headers,
+                });
+                if until_continue && status == StatusCode::CONTINUE {
+                    return Ok(None);
+                }
+                continue;
+            }
+            head.informational = std::mem::take(informational);
+            return Ok(Some(head));
+        }
+        if buf.len() > MAX_HEAD_SIZE {
+            return Err(Error::InvalidHeader(format!("response head exceeds {} bytes", MAX_HEAD_SIZE)));
+        }
+        buf.reserve(READ_CHUNK_SIZE);
+        if io.read_buf(buf).await? == 0 {
+            // Nothing at all arriving is reported as an I/O error so a stale
+            // pooled connection can be told apart from a truncated response.
+            if buf.is_empty() && informational.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before response").into());
+            }
+            return Err(Error::UnexpectedEof);
+        }
+    }
+}
+
+/// Whether the request asks the server to confirm with 100 Continue before
+/// its body is sent.
+pub fn expects_continue(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(EXPECT)
+        .iter()
+        .any(|value| value.as_bytes().trim_ascii().eq_ignore_ascii_case(b"100-continue"))
+}
+
+/// Responses that never carry content, whatever their headers say
+/// (RFC 9112 section 6.3).
+pub fn has_no_body(method: &Method, status: StatusCode) -> bool {
+    method == Method::HEAD
+        || status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+        || (method == Method::CONNECT && status.is_success())
+}
+
+/// How the end of a response body is found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+    Length(u64),
+    Chunked,
+    UntilClose,
+}
+
+impl Framing {
+    /// Applies the message length rules of RFC 9112 section 6.3 to a response
+    /// to a `method` request.
+    pub fn of(head: &ResponseHead, method: &Method) -> Result<Framing, Error> {
+        if has_no_body(method, head.status) {
+            return Ok(Framing::Length(0));
+        }
+        let mut codings = head
+            .headers
+            .get_all(TRANSFER_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|coding| !coding.is_empty())
+            .peekable();
+        if codings.peek().is_some() {
+            return Ok(match codings.last() {
+                Some(coding) if coding.eq_ignore_ascii_case("chunked") => Framing::Chunked,
+                _ => Framing::UntilClose,
+            });
+        }
+
+        let mut length = None;
+        for value in head.headers.get_all(CONTENT_LENGTH) {
+            let invalid = || Error::InvalidHeader(format!("content-length: {}", String::from_utf8_lossy(value.as_bytes())));
+            for part in value.to_str().map_err(|_| invalid())?.split(',') {
+                let part = part.trim();
+                if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(invalid());
+                }
+                let part = part.parse::<u64>().map_err(|_| invalid())?;
+                if length.is_some_and(|length| length != part) {
+                    return Err(invalid());
+                }
+                length = Some(part);
+            }
+        }
+        Ok(length.map_or(Framing::UntilClose, Framing::Length))
+    }
+}
+
+/// Pulls a response body off the connection one chunk at a time.
+pub struct BodyReader<S> {
+    io: S,
+    state: ReadState,
+    buf: BytesMut,
+    limit: Option<u64>,
+    received: u64,
+    trailers: HeaderMap,
+    close_delimited: bool,
+}
+
+enum ReadState {
+    Length(u64),
+    Chunked(ChunkedDecoder),
+    UntilClose,
+    Done,
+}
+
+impl<S> BodyReader<S>
+where
+    S: AsyncRead + Unpin,
+{
+    /// `buf` holds body bytes already read along with the head.
+    pub fn new(io: S, framing: Framing, buf: BytesMut, limit: Option<u64>) -> Self {
+        let state = match framing {
+            Framing::Length(length) => ReadState::Length(length),
+            Framing::Chunked => ReadState::Chunked(ChunkedDecoder::new()),
+            Framing::UntilClose => ReadState::UntilClose,
+        };
+        BodyReader {
+            io,
+            state,
+            buf,
+            limit,
+            received: 0,
+            trailers: HeaderMap::new(),
+            close_delimited: framing == Framing::UntilClose,
+        }
+    }
+
+    pub async fn next_chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        loop {
+            let chunk = match &mut self.state {
+                ReadState::Length(0) => {
+                    self.state = ReadState::Done;
+                    None
+                }
+                ReadState::Length(remaining) if !self.buf.is_empty() => {
+                    let n = (*remaining).min(self.buf.len() as u64);
+                    *remaining -= n;
+                    Some(self.buf.split_to(n as usize).freeze())
+                }
+                ReadState::Chunked(decoder) => {
+                    let mut out = Vec::new();
+                    decoder.decode(&mut self.buf, &mut out)?;
+                    if !out.is_empty() {
+                        Some(Bytes::from(out))
+                    } else if decoder.is_done() {
+                        let ReadState::Chunked(decoder) = std::mem::replace(&mut self.state, ReadState::Done) else {
+                            unreachable!()
+                        };
+                        self.trailers = decoder.into_trailers();
+                        None
+                    } else {
+                        self.fill().await?;
+                        continue;
+                    }
+                }
+                ReadState::UntilClose if !self.buf.is_empty() => Some(self.buf.split().freeze()),
+                ReadState::UntilClose => {
+                    if !self.fill().await? {
+                        self.state = ReadState::Done;
+                    }
+                    continue;
+                }
+                ReadState::Length(_) => {
+                    self.fill().await?;
+                    continue;
+                }
+                ReadState::Done => None,
+            };
+
+            if let Some(chunk) = &chunk {
+                self.received += chunk.len() as u64;
+                if let Some(limit) = self.limit.filter(|&limit| self.received > limit) {
+                    return Err(Error::BodyTooLarge { limit });
+                }
+            }
+            return Ok(chunk);
+        }
+    }
+
+    /// Reads more bytes into the buffer, returning `false` at a clean EOF of a
+    /// close-delimited body. EOF anywhere else is an error.
+    async fn fill(&mut self) -> Result<bool, Error> {
+        self.buf.reserve(READ_CHUNK_SIZE);
+        if self.io.read_buf(&mut self.buf).await? > 0 {
+            return Ok(true);
+        }
+        match self.state {
+            ReadState::UntilClose => Ok(false),
+            _ => Err(Error::UnexpectedEof),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, ReadState::Done)
+    }
+
+    /// Gives back the connection and trailers, and whether the connection sits
+    /// exactly at the end of this message and so can carry another request.
+    pub fn into_parts(self) -> (S, HeaderMap, bool) {
+        let reusable = self.is_done() && !self.close_delimited && self.buf.is_empty();
+        (self.io, self.trailers, reusable)
+    }
+}
+
+fn parse_status_line(line: &[u8]) -> Result<(Version, StatusCode, String), Error> {
+    let malformed = || Error::MalformedStatusLine(String::from_utf8_lossy(line).into_owned());
+    let mut parts = line.splitn(3, |&b| b == b' ');
+    let version = match parts.next() {
+        Some(b"HTTP/1.1") => Version::HTTP_11,
+        Some(b"HTTP/1.0") => Version::HTTP_10,
+        _ => return Err(malformed()),
+    };
+    let status = parts
+        .next()
+        .filter(|code| code.len() == 3)
+        .and_then(|code| StatusCode::from_bytes(code).ok())
+        .ok_or_else(malformed)?;
+    let reason = String::from_utf8_lossy(parts.next().unwrap_or_default()).into_owned();
+    Ok((version, status, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Body;
+
+    fn target(method: Method, url: &str, via_proxy: bool) -> String {
+        let url = Url::parse(url).unwrap();
+        request_target(&url, TargetForm::select(&method, &url, via_proxy))
+    }
+
+    #[test]
+    fn origin_form_keeps_query_and_drops_fragment() {
+        assert_eq!(target(Method::GET, "http://example.com/items?page=2&q=x#top", false), "/items?page=2&q=x");
+        assert_eq!(target(Method::GET, "http://example.com", false), "/");
+    }
+
+    #[test]
+    fn absolute_form_for_proxies() {
+        assert_eq!(
+            target(Method::GET, "http://user:pw@example.com:8080/a?b=c#d", true),
+            "http://example.com:8080/a?b=c"
+        );
+    }
+
+    #[test]
+    fn authority_and_asterisk_forms() {
+        assert_eq!(target(Method::CONNECT, "https://example.com/ignored", false), "example.com:443");
+        assert_eq!(target(Method::CONNECT, "http://[::1]:8443", true), "[::1]:8443");
+        assert_eq!(target(Method::OPTIONS, "http://example.com/*", false), "*");
+        assert_eq!(target(Method::OPTIONS, "http://example.com/path", false), "/path");
+    }
+
+    fn head(request: &Request) -> String {
+        let form = TargetForm::select(&request.method, &request.uri, false);
+        String::from_utf8(encode_request_head(request, &request.uri, form).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn writes_values_verbatim_in_caller_order_and_casing() {
+        let mut request = Request {
+            uri: Url::parse("http://example.com:8080/search?q=1").unwrap(),
+            ..Request::default()
+        };
+        request.append_header("X-Trace-ID", HeaderValue::from_static("abc")).unwrap();
+        request.append_header("accept", HeaderValue::from_static("text/html")).unwrap();
+        request.append_header("X-Trace-ID", HeaderValue::from_static("def")).unwrap();
+        request.headers.insert("user-agent", HeaderValue::from_static("kusari"));
+        request.body = Body::from("hi");
+
+        assert_eq!(
+            head(&request),
+            "GET /search?q=1 HTTP/1.1\r\n\
+             Host: example.com:8080\r\n\
+             X-Trace-ID: abc\r\n\
+             accept: text/html\r\n\
+             X-Trace-ID: def\r\n\
+             user-agent: kusari\r\n\
+             Content-Length: 2\r\n\
+             \r\n"
+        );
+    }
+
+    #[test]
+    fn caller_supplied_host_and_framing_headers_win() {
+        let mut request = Request::default();
+        request.headers.insert(HOST, HeaderValue::from_static("virtual.test"));
+        request.headers.insert(TRANSFER_ENCODING, HeaderValue::from_static("gzip, chunked"));
+        request.body = Body::from_stream(futures_util::stream::empty());
+
+        assert_eq!(head(&request), "GET / HTTP/1.1\r\nhost: virtual.test\r\ntransfer-encoding: gzip, chunked\r\n\r\n");
+    }
+
+    #[test]
+    fn sends_zero_length_for_empty_posts() {
+        let mut request = Request {
+            method: Method::POST,
+            ..Request::default()
+        };
+        assert_eq!(head(&request), "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n");
+
+        request.method = Method::PUT;
+        request.headers.insert(CONTENT_LENGTH, HeaderValue::from_static("0"));
+        assert_eq!(head(&request), "PUT / HTTP/1.1\r\nHost: localhost\r\ncontent-length: 0\r\n\r\n");
+
+        request.method = Method::GET;
+        request.headers.clear();
+        assert_eq!(head(&request), "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    }
+
+    #[test]
+    fn rejects_header_injection() {
+        let mut request = Request::default();
+        let err = request.append_header("X-Evil\r\nInjected", HeaderValue::from_static("yes")).unwrap_err();
+        assert!(matches!(err, Error::InvalidHeader(_)));
+        assert!(HeaderValue::from_str("a\r\nInjected: yes").is_err());
+
+        let mut out = Vec::new();
+        write_header(&mut out, "X-Ok", &HeaderValue::from_static("a\tb")).unwrap();
+        assert_eq!(out, b"X-Ok: a\tb\r\n");
+    }
+
+    #[test]
+    fn parses_repeated_folded_and_unspaced_headers() {
+        let head = parse_response_head(
+            b"HTTP/1.1 200 OK\r\n\
+              Set-Cookie: a=1\r\n\
+              Vary:Accept\r\n\
+              Set-Cookie: b=2\r\n\
+              X-Folded: first\r\n\
+              \t  second\r\n\
+              \r\n",
+        )
+        .unwrap();
+
+        assert_eq!(head.status, StatusCode::OK);
+        assert_eq!(head.reason, "OK");
+        let cookies: Vec<_> = head.headers.get_all("set-cookie").iter().collect();
+        assert_eq!(cookies, ["a=1", "b=2"]);
+        assert_eq!(head.headers["vary"], "Accept");
+        assert_eq!(head.headers["x-folded"], "first second");
+        let names: Vec<_> = head.raw_headers.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["Set-Cookie", "Vary", "Set-Cookie", "X-Folded"]);
+    }
+
+    #[test]
+    fn rejects_malformed_status_line_and_leading_fold() {
+        assert!(matches!(parse_response_head(b"HTTP/1.1 OK\r\n\r\n"), Err(Error::MalformedStatusLine(_))));
+        assert!(matches!(parse_response_head(b"HTTP/1.1 200 OK\r\n folded\r\n\r\n"), Err(Error::InvalidHeader(_))));
+    }
+
+    #[tokio::test]
+    async fn collects_interim_responses_before_the_final_head() {
+        let mut wire: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n\
+              HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n\
+              HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        let (head, rest) = read_head(&mut wire).await.unwrap();
+        assert_eq!(head.status, StatusCode::OK);
+        assert_eq!(&rest[..], b"ok");
+        let interim: Vec<_> = head.informational.iter().map(|info| info.status.as_u16()).collect();
+        assert_eq!(interim, [100, 103]);
+        assert_eq!(head.informational[1].headers["link"], "</style.css>; rel=preload");
+
+        let mut truncated: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+        assert!(matches!(read_head(&mut truncated).await, Err(Error::UnexpectedEof)));
+    }
+
+    #[tokio::test]
+    async fn accepts_heads_ending_in_bare_line_feeds() {
+        let mut wire = &b"HTTP/1.1 200 OK\nContent-Length: 2\n\nok"[..];
+        let (head, rest) = read_head(&mut wire).await.unwrap();
+        assert_eq!(head.headers["content-length"], "2");
+        assert_eq!(&rest[..], b"ok");
+
+        let mut wire = &b"HTTP/1.1 204 No Content\r\nX-A: 1\n\r\nnext"[..];
+        let (head, rest) = read_head(&mut wire).await.unwrap();
+        assert_eq!(head.headers["x-a"], "1");
+        assert_eq!(&rest[..], b"next");
+    }
+
+    #[test]
+    fn bodiless_responses_ignore_length_headers() {
+        let head = parse_response_head(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n").unwrap();
+        assert_eq!(Framing::of(&head, &Method::GET).unwrap(), Framing::Length(10));
+        assert_eq!(Framing::of(&head, &Method::HEAD).unwrap(), Framing::Length(0));
+        for status in ["204 No Content", "304 Not Modified"] {
+            let head = parse_response_head(format!("HTTP/1.1 {}\r\nTransfer-Encoding: chunked\r\n\r\n", status).as_bytes()).unwrap();
+            assert_eq!(Framing::of(&head, &Method::GET).unwrap(), Framing::Length(0));
+        }
+        let head = parse_response_head(b"HTTP/1.0 200 OK\r\n\r\n").unwrap();
+        assert_eq!(Framing::of(&head, &Method::GET).unwrap(), Framing::UntilClose);
+    }
+}
@@ -0,0 +1,1284 @@
+//! HTTP/2 client connections (RFC 9113).
+//!
+//! Each connection runs as two tasks: one reads and parses frames, the other
+//! owns the connection state and the write half. Requests talk to the state
+//! task through a channel, and the response for each stream comes back on a
+//! channel of its own, so any number of requests can share one connection.
+
+use crate::body::{Body, Frame};
+use crate::hpack::{self, Decoder};
+use crate::http1::{authority, has_no_body, request_target, ResponseHead, TargetForm};
+use crate::timeout::{timeout, TimeoutKind, Timeouts};
+use crate::tls::TlsInfo;
+use crate::{Error, InformationalResponse, Request, ResponseBody};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_util::{stream, StreamExt};
+use http::header::{AUTHORIZATION, CONNECTION, CONTENT_LENGTH, HOST, TE, TRANSFER_ENCODING, UPGRADE};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Version};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use url::Url;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+const MAX_STREAM_ID: u32 = (1 << 31) - 1;
+const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+const HEADER_TABLE_SIZE: usize = 4096;
+const MAX_HEADER_LIST_SIZE: usize = 64 * 1024;
+/// Receive windows we advertise. Each stream may have this much unread data
+/// buffered before the server has to wait for the caller to read it.
+const STREAM_WINDOW: i64 = 1 << 20;
+const CONNECTION_WINDOW: i64 = 4 << 20;
+
+/// An HTTP/2 error code, sent in `RST_STREAM` and `GOAWAY` frames.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Reason(u32);
+
+impl Reason {
+    pub const NO_ERROR: Reason = Reason(0x0);
+    pub const PROTOCOL_ERROR: Reason = Reason(0x1);
+    pub const INTERNAL_ERROR: Reason = Reason(0x2);
+    pub const FLOW_CONTROL_ERROR: Reason = Reason(0x3);
+    pub const SETTINGS_TIMEOUT: Reason = Reason(0x4);
+    pub const STREAM_CLOSED: Reason = Reason(0x5);
+    pub const FRAME_SIZE_ERROR: Reason = Reason(0x6);
+    /// The server did not process the request; it is always safe to retry.
+    pub const REFUSED_STREAM: Reason = Reason(0x7);
+    pub const CANCEL: Reason = Reason(0x8);
+    pub const COMPRESSION_ERROR: Reason = Reason(0x9);
+    pub const CONNECT_ERROR: Reason = Reason(0xa);
+    pub const ENHANCE_YOUR_CALM: Reason = Reason(0xb);
+    pub const INADEQUATE_SECURITY: Reason = Reason(0xc);
+    pub const HTTP_1_1_REQUIRED: Reason = Reason(0xd);
+
+    pub fn code(&self) -> u32 {
+        self.0
+    }
+
+    fn name(&self) -> Option<&'static str> {
+        const NAMES: [&str; 14] = [
+            "NO_ERROR",
+            "PROTOCOL_ERROR",
+            "INTERNAL_ERROR",
+            "FLOW_CONTROL_ERROR",
+            "SETTINGS_TIMEOUT",
+            "STREAM_CLOSED",
+            "FRAME_SIZE_ERROR",
+            "REFUSED_STREAM",
+            "CANCEL",
+            "COMPRESSION_ERROR",
+            "CONNECT_ERROR",
+            "ENHANCE_YOUR_CALM",
+            "INADEQUATE_SECURITY",
+            "HTTP_1_1_REQUIRED",
+        ];
+        NAMES.get(self.0 as usize).copied()
+    }
+}
+
+impl From<u32> for Reason {
+    fn from(code: u32) -> Self {
+        Reason(code)
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "unknown error code {:#x}", self.0),
+        }
+    }
+}
+
+impl fmt::Debug for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+struct RawFrame {
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: Bytes,
+}
+
+/// Splits one frame off the front of `buf` once it has fully arrived.
+fn parse_frame(buf: &mut BytesMut, max_size: usize) -> Result<Option<RawFrame>, Reason> {
+    if buf.len() < 9 {
+        return Ok(None);
+    }
+    let len = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]) as usize;
+    if len > max_size {
+        return Err(Reason::FRAME_SIZE_ERROR);
+    }
+    if buf.len() < 9 + len {
+        return Ok(None);
+    }
+    let mut head = buf.split_to(9);
+    let len = head.get_uint(3) as usize;
+    let kind = head.get_u8();
+    let flags = head.get_u8();
+    let stream = head.get_u32() & 0x7fff_ffff;
+    Ok(Some(RawFrame {
+        kind,
+        flags,
+        stream,
+        payload: buf.split_to(len).freeze(),
+    }))
+}
+
+fn write_frame_head(out: &mut BytesMut, len: usize, kind: u8, flags: u8, stream: u32) {
+    out.put_uint(len as u64, 3);
+    out.put_u8(kind);
+    out.put_u8(flags);
+    out.put_u32(stream);
+}
+
+fn write_frame(out: &mut BytesMut, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+    write_frame_head(out, payload.len(), kind, flags, stream);
+    out.extend_from_slice(payload);
+}
+
+/// Writes a header block as HEADERS plus as many CONTINUATION frames as the
+/// peer's frame size requires.
+fn write_header_block(out: &mut BytesMut, max_frame_size: usize, stream: u32, mut block: Bytes, end_stream: bool) {
+    let mut kind = HEADERS;
+    let mut flags = if end_stream { END_STREAM } else { 0 };
+    loop {
+        let fragment = block.split_to(block.len().min(max_frame_size));
+        if block.is_empty() {
+            flags |= END_HEADERS;
+        }
+        write_frame(out, kind, flags, stream, &fragment);
+        if block.is_empty() {
+            return;
+        }
+        kind = CONTINUATION;
+        flags = 0;
+    }
+}
+
+/// Removes the padding from a `PADDED` frame.
+fn strip_padding(flags: u8, mut payload: Bytes) -> Result<Bytes, Reason> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+    let pad = *payload.first().ok_or(Reason::PROTOCOL_ERROR)? as usize;
+    if pad >= payload.len() {
+        return Err(Reason::PROTOCOL_ERROR);
+    }
+    payload.advance(1);
+    payload.truncate(payload.len() - pad);
+    Ok(payload)
+}
+
+/// Headers that only mean something for a single HTTP/1.1 connection and
+/// are forbidden in HTTP/2.
+fn is_connection_specific(name: &HeaderName) -> bool {
+    name == CONNECTION
+        || name == TRANSFER_ENCODING
+        || name == UPGRADE
+        || name.as_str() == "keep-alive"
+        || name.as_str() == "proxy-connection"
+}
+
+/// Encodes the request head as an HPACK header block.
+fn encode_request(request: &Request, url: &Url) -> Result<Bytes, Error> {
+    let mut block = BytesMut::with_capacity(256);
+    let authority = match request.headers.get(HOST) {
+        Some(host) => host.as_bytes().to_vec(),
+        None => authority(url, false).into_bytes(),
+    };
+    hpack::encode_field(&mut block, b":method", request.method.as_str().as_bytes(), false);
+    match TargetForm::select(&request.method, url, false) {
+        TargetForm::Authority => {}
+        form => {
+            hpack::encode_field(&mut block, b":scheme", url.scheme().as_bytes(), false);
+            hpack::encode_field(&mut block, b":path", request_target(url, form).as_bytes(), false);
+        }
+    }
+    hpack::encode_field(&mut block, b":authority", &authority, false);
+
+    for (name, value) in &request.headers {
+        if name == HOST || is_connection_specific(name) || (name == TE && value != "trailers") {
+            continue;
+        }
+        if value.as_bytes().iter().any(|&b| b == b'\r' || b == b'\n' || b == b'\0') {
+            return Err(Error::InvalidHeader(name.to_string()));
+        }
+        let sensitive = value.is_sensitive() || name == AUTHORIZATION;
+        hpack::encode_field(&mut block, name.as_str().as_bytes(), value.as_bytes(), sensitive);
+    }
+    if !request.headers.contains_key(CONTENT_LENGTH) {
+        if let Some(len) = request.body.len().filter(|&len| len > 0) {
+            hpack::encode_field(&mut block, b"content-length", len.to_string().as_bytes(), false);
+        }
+    }
+    Ok(block.freeze())
+}
+
+/// Turns a decoded response header block into a head, rejecting the
+/// malformed messages RFC 9113 section 8.1.1 lists.
+fn decode_response(fields: Vec<(Bytes, Bytes)>) -> Result<ResponseHead, Reason> {
+    let mut status = None;
+    let mut headers = HeaderMap::with_capacity(fields.len());
+    let mut raw_headers = Vec::with_capacity(fields.len());
+    for (name, value) in fields {
+        if name.first() == Some(&b':') {
+            if name != ":status" || status.is_some() || !headers.is_empty() {
+                return Err(Reason::PROTOCOL_ERROR);
+            }
+            status = Some(StatusCode::from_bytes(&value).map_err(|_| Reason::PROTOCOL_ERROR)?);
+            continue;
+        }
+        if name.iter().any(u8::is_ascii_uppercase) {
+            return Err(Reason::PROTOCOL_ERROR);
+        }
+        let name = HeaderName::from_bytes(&name).map_err(|_| Reason::PROTOCOL_ERROR)?;
+        let value = HeaderValue::from_maybe_shared(value).map_err(|_| Reason::PROTOCOL_ERROR)?;
+        if is_connection_specific(&name) {
+            return Err(Reason::PROTOCOL_ERROR);
+        }
+        raw_headers.push((name.as_str().to_string(), value.clone()));
+        headers.append(name, value);
+    }
+    let status = status.ok_or(Reason::PROTOCOL_ERROR)?;
+    Ok(ResponseHead {
+        version: Version::HTTP_2,
+        status,
+        reason: status.canonical_reason().unwrap_or_default().to_string(),
+        headers,
+        raw_headers,
+        informational: Vec::new(),
+    })
+}
+
+fn decode_trailers(fields: Vec<(Bytes, Bytes)>) -> Result<HeaderMap, Reason> {
+    let mut trailers = HeaderMap::with_capacity(fields.len());
+    for (name, value) in fields {
+        if name.first() == Some(&b':') || name.iter().any(u8::is_ascii_uppercase) {
+            return Err(Reason::PROTOCOL_ERROR);
+        }
+        let name = HeaderName::from_bytes(&name).map_err(|_| Reason::PROTOCOL_ERROR)?;
+        let value = HeaderValue::from_maybe_shared(value).map_err(|_| Reason::PROTOCOL_ERROR)?;
+        trailers.append(name, value);
+    }
+    Ok(trailers)
+}
+
+fn content_length(headers: &HeaderMap) -> Result<Option<u64>, Reason> {
+    headers
+        .get(CONTENT_LENGTH)
+        .map(|value| value.to_str().ok().and_then(|v| v.trim().parse().ok()).ok_or(Reason::PROTOCOL_ERROR))
+        .transpose()
+}
+
+/// What the connection task sends back for a stream.
+enum Event {
+    Informational(InformationalResponse),
+    Head(ResponseHead),
+    Data(Bytes),
+    Trailers(HeaderMap),
+    Error(Error),
+}
+
+enum Command {
+    Open {
+        method: Method,
+        block: Bytes,
+        end_stream: bool,
+        events: mpsc::UnboundedSender<Event>,
+        opened: oneshot::Sender<Result<u32, Error>>,
+    },
+    Data {
+        id: u32,
+        data: Bytes,
+        end_stream: bool,
+        sent: oneshot::Sender<Result<(), Error>>,
+    },
+    Trailers {
+        id: u32,
+        block: Bytes,
+    },
+    /// The caller has read this much response data; its window can reopen.
+    Release {
+        id: u32,
+        len: usize,
+    },
+    /// The caller is done with the stream, whether or not it finished.
+    Cancel {
+        id: u32,
+    },
+}
+
+enum Outgoing {
+    Data {
+        data: Bytes,
+        end_stream: bool,
+        sent: oneshot::Sender<Result<(), Error>>,
+    },
+    Trailers(Bytes),
+}
+
+struct Stream {
+    events: Option<mpsc::UnboundedSender<Event>>,
+    queue: VecDeque<Outgoing>,
+    send_window: i64,
+    recv_window: i64,
+    /// Data read by the caller that has not been announced in a WINDOW_UPDATE yet.
+    recv_released: i64,
+    /// Data handed to the caller and not read yet.
+    buffered: usize,
+    local_closed: bool,
+    remote_closed: bool,
+    reset: bool,
+    /// The request method, which decides whether the response may have content.
+    method: Method,
+    head_received: bool,
+    expected_length: Option<u64>,
+    received_length: u64,
+}
+
+impl Stream {
+    fn is_active(&self) -> bool {
+        !(self.reset || (self.local_closed && self.remote_closed))
+    }
+
+    fn send(&self, event: Event) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+}
+
+struct Pending {
+    method: Method,
+    block: Bytes,
+    end_stream: bool,
+    events: mpsc::UnboundedSender<Event>,
+    opened: oneshot::Sender<Result<u32, Error>>,
+}
+
+struct Shared {
+    closed: AtomicBool,
+}
+
+/// A handle for sending requests on an HTTP/2 connection. Clones share the
+/// connection, which closes once every handle and response is gone.
+#[derive(Clone)]
+pub(crate) struct SendRequest {
+    commands: mpsc::UnboundedSender<Command>,
+    shared: Arc<Shared>,
+    tls_info: Option<Arc<TlsInfo>>,
+}
+
+/// Starts an HTTP/2 connection over `io`, which must already be known to
+/// speak HTTP/2: negotiated through ALPN, or assumed with prior knowledge.
+/// `tls_info` describes `io`'s TLS session, if any, for the responses.
+pub(crate) async fn handshake<T>(io: T, tls_info: Option<TlsInfo>, idle_timeout: Option<Duration>) -> Result<SendRequest, Error>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, mut writer) = tokio::io::split(io);
+
+    let mut out = BytesMut::with_capacity(64);
+    out.extend_from_slice(PREFACE);
+    let settings = [
+        (SETTINGS_ENABLE_PUSH, 0),
+        (SETTINGS_INITIAL_WINDOW_SIZE, STREAM_WINDOW as u32),
+        (SETTINGS_MAX_HEADER_LIST_SIZE, MAX_HEADER_LIST_SIZE as u32),
+    ];
+    write_frame_head(&mut out, settings.len() * 6, SETTINGS, 0, 0);
+    for (id, value) in settings {
+        out.put_u16(id);
+        out.put_u32(value);
+    }
+    write_frame(&mut out, WINDOW_UPDATE, 0, 0, &((CONNECTION_WINDOW - DEFAULT_WINDOW) as u32).to_be_bytes());
+    writer.write_all(&out).await?;
+    writer.flush().await?;
+
+    let (frames_tx, frames) = mpsc::unbounded_channel();
+    let (commands_tx, commands) = mpsc::unbounded_channel();
+    let shared = Arc::new(Shared {
+        closed: AtomicBool::new(false),
+    });
+    let reader = tokio::spawn(read_frames(reader, frames_tx));
+    let connection = Connection {
+        writer,
+        out: BytesMut::new(),
+        decoder: Decoder::new(HEADER_TABLE_SIZE),
+        streams: HashMap::new(),
+        pending: VecDeque::new(),
+        next_id: 1,
+        max_concurrent: usize::MAX,
+        initial_window: DEFAULT_WINDOW,
+        max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        send_window: DEFAULT_WINDOW,
+        recv_window: CONNECTION_WINDOW,
+        recv_released: 0,
+        continuation: None,
+        going_away: false,
+        shared: shared.clone(),
+    };
+    tokio::spawn(async move {
+        connection.run(frames, commands, idle_timeout).await;
+        reader.abort();
+    });
+    Ok(SendRequest {
+        commands: commands_tx,
+        shared,
+        tls_info: tls_info.map(Arc::new),
+    })
+}
+
+async fn read_frames<T: AsyncRead>(mut reader: ReadHalf<T>, frames: mpsc::UnboundedSender<Result<RawFrame, Error>>) {
+    let mut buf = BytesMut::with_capacity(DEFAULT_MAX_FRAME_SIZE + 9);
+    loop {
+        match parse_frame(&mut buf, DEFAULT_MAX_FRAME_SIZE) {
+            Ok(Some(frame)) => {
+                if frames.send(Ok(frame)).is_err() {
+                    return;
+                }
+                continue;
+            }
+            Ok(None) => {}
+            Err(reason) => {
+                let _ = frames.send(Err(Error::Http2(reason)));
+                return;
+            }
+        }
+        match reader.read_buf(&mut buf).await {
+            Ok(0) => {
+                let _ = frames.send(Err(Error::Io(io::ErrorKind::UnexpectedEof.into())));
+                return;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                let _ = frames.send(Err(err.into()));
+                return;
+            }
+        }
+    }
+}
+
+/// Makes a fresh copy of a connection-level error for every stream it fails.
+fn copy_error(err: &Error) -> Error {
+    match err {
+        Error::Http2(reason) => Error::Http2(*reason),
+        Error::Io(err) => Error::Io(io::Error::new(err.kind(), err.to_string())),
+        Error::Timeout(kind) => Error::Timeout(*kind),
+        other => Error::Io(io::Error::other(other.to_string())),
+    }
+}
+
+struct Connection<T> {
+    writer: WriteHalf<T>,
+    out: BytesMut,
+    decoder: Decoder,
+    streams: HashMap<u32, Stream>,
+    pending: VecDeque<Pending>,
+    next_id: u32,
+    max_concurrent: usize,
+    initial_window: i64,
+    max_frame_size: usize,
+    send_window: i64,
+    recv_window: i64,
+    recv_released: i64,
+    /// A header block waiting for CONTINUATION frames: stream, fragments, END_STREAM.
+    continuation: Option<(u32, BytesMut, bool)>,
+    going_away: bool,
+    shared: Arc<Shared>,
+}
+
+impl<T: AsyncWrite> Connection<T> {
+    async fn run(
+        mut self,
+        mut frames: mpsc::UnboundedReceiver<Result<RawFrame, Error>>,
+        mut commands: mpsc::UnboundedReceiver<Command>,
+        idle_timeout: Option<Duration>,
+    ) {
+        let mut handles_gone = false;
+        let result = loop {
+            let idle = self.streams.is_empty() && self.pending.is_empty();
+            let step = tokio::select! {
+                frame = frames.recv() => match frame {
+                    Some(Ok(frame)) => self.on_frame(frame),
+                    Some(Err(err)) => Err(err),
+                    None => Err(Error::UnexpectedEof),
+                },
+                command = commands.recv(), if !handles_gone => {
+                    match command {
+                        Some(command) => self.on_command(command),
+                        None => handles_gone = true,
+                    }
+                    Ok(())
+                }
+                _ = tokio::time::sleep(idle_timeout.unwrap_or(Duration::MAX)), if idle && idle_timeout.is_some() => {
+                    break Ok(());
+                }
+            };
+            if let Err(err) = step {
+                break Err(err);
+            }
+            self.open_pending();
+            self.flush_streams();
+            if let Err(err) = self.write_out().await {
+                break Err(err);
+            }
+            if (handles_gone || self.going_away) && self.streams.is_empty() && self.pending.is_empty() {
+                break Ok(());
+            }
+        };
+
+        self.shared.closed.store(true, Ordering::Release);
+        let reason = match &result {
+            Ok(()) => Reason::NO_ERROR,
+            Err(Error::Http2(reason)) => *reason,
+            Err(_) => Reason::INTERNAL_ERROR,
+        };
+        let err = result.err().unwrap_or(Error::Http2(Reason::CANCEL));
+        for (_, stream) in self.streams.drain() {
+            stream.send(Event::Error(copy_error(&err)));
+            for outgoing in stream.queue {
+                if let Outgoing::Data { sent, .. } = outgoing {
+                    let _ = sent.send(Err(copy_error(&err)));
+                }
+            }
+        }
+        for pending in self.pending.drain(..) {
+            let _ = pending.opened.send(Err(Error::Http2(Reason::REFUSED_STREAM)));
+        }
+        // The last stream field counts server-initiated streams, and push is off.
+        let mut payload = [0; 8];
+        payload[4..].copy_from_slice(&reason.code().to_be_bytes());
+        write_frame(&mut self.out, GOAWAY, 0, 0, &payload);
+        let _ = self.write_out().await;
+        let _ = self.writer.shutdown().await;
+    }
+
+    async fn write_out(&mut self) -> Result<(), Error> {
+        if !self.out.is_empty() {
+            self.writer.write_all(&self.out).await?;
+            self.writer.flush().await?;
+            self.out.clear();
+        }
+        Ok(())
+    }
+
+    fn on_command(&mut self, command: Command) {
+        match command {
+            Command::Open {
+                method,
+                block,
+                end_stream,
+                events,
+                opened,
+            } => {
+                if self.going_away || self.next_id > MAX_STREAM_ID {
+                    let _ = opened.send(Err(Error::Http2(Reason::REFUSED_STREAM)));
+                    return;
+                }
+                self.pending.push_back(Pending {
+                    method,
+                    block,
+                    end_stream,
+                    events,
+                    opened,
+                });
+            }
+            Command::Data {
+                id,
+                data,
+                end_stream,
+                sent,
+            } => match self.streams.get_mut(&id) {
+                Some(stream) if !stream.reset && !stream.local_closed => {
+                    stream.queue.push_back(Outgoing::Data { data, end_stream, sent })
+                }
+                _ => {
+                    let _ = sent.send(Err(Error::Http2(Reason::STREAM_CLOSED)));
+                }
+            },
+            Command::Trailers { id, block } => {
+                if let Some(stream) = self.streams.get_mut(&id).filter(|s| !s.reset && !s.local_closed) {
+                    stream.queue.push_back(Outgoing::Trailers(block));
+                }
+            }
+            Command::Release { id, len } => {
+                self.release_connection(len as i64);
+                if let Some(stream) = self.streams.get_mut(&id) {
+                    stream.buffered = stream.buffered.saturating_sub(len);
+                    if !stream.remote_closed && !stream.reset {
+                        stream.recv_released += len as i64;
+                        if stream.recv_released >= STREAM_WINDOW / 2 {
+                            let increment = std::mem::take(&mut stream.recv_released);
+                            stream.recv_window += increment;
+                            write_frame(&mut self.out, WINDOW_UPDATE, 0, id, &(increment as u32).to_be_bytes());
+                        }
+                    }
+                }
+            }
+            Command::Cancel { id } => {
+                if let Some(stream) = self.streams.remove(&id) {
+                    if stream.is_active() {
+                        write_frame(&mut self.out, RST_STREAM, 0, id, &Reason::CANCEL.code().to_be_bytes());
+                    }
+                    self.release_connection(stream.buffered as i64);
+                }
+            }
+        }
+    }
+
+    fn release_connection(&mut self, len: i64) {
+        self.recv_released += len;
+        if self.recv_released >= CONNECTION_WINDOW / 2 {
+            let increment = std::mem::take(&mut self.recv_released);
+            self.recv_window += increment;
+            write_frame(&mut self.out, WINDOW_UPDATE, 0, 0, &(increment as u32).to_be_bytes());
+        }
+    }
+
+    /// Opens queued streams while the server's concurrency limit allows.
+    fn open_pending(&mut self) {
+        while !self.pending.is_empty() {
+            let active = self.streams.values().filter(|s| s.is_active()).count();
+            if active >= self.max_concurrent {
+                return;
+            }
+            let Some(pending) = self.pending.pop_front() else { return };
+            if pending.opened.is_closed() {
+                continue;
+            }
+            let id = self.next_id;
+            self.next_id += 2;
+            write_header_block(&mut self.out, self.max_frame_size, id, pending.block, pending.end_stream);
+            self.streams.insert(
+                id,
+                Stream {
+                    events: Some(pending.events),
+                    queue: VecDeque::new(),
+                    send_window: self.initial_window,
+                    recv_window: STREAM_WINDOW,
+                    recv_released: 0,
+                    buffered: 0,
+                    local_closed: pending.end_stream,
+                    remote_closed: false,
+                    reset: false,
+                    method: pending.method,
+                    head_received: false,
+                    expected_length: None,
+                    received_length: 0,
+                },
+            );
+            let _ = pending.opened.send(Ok(id));
+        }
+    }
+
+    /// Moves queued request data into DATA frames as far as the flow-control
+    /// windows allow.
+    fn flush_streams(&mut self) {
+        let mut ids: Vec<u32> = self.streams.iter().filter(|(_, s)| !s.queue.is_empty()).map(|(&id, _)| id).collect();
+        ids.sort_unstable();
+        for id in ids {
+            let Some(stream) = self.streams.get_mut(&id) else { continue };
+            while let Some(outgoing) = stream.queue.front_mut() {
+                match outgoing {
+                    Outgoing::Data { data, end_stream, .. } => {
+                        let window = stream.send_window.min(self.send_window).min(self.max_frame_size as i64);
+                        if !data.is_empty() && window <= 0 {
+                            break;
+                        }
+                        let chunk = data.split_to(data.len().min(window.max(0) as usize));
+                        let last = data.is_empty();
+                        let flags = if last && *end_stream { END_STREAM } else { 0 };
+                        if !chunk.is_empty() || flags != 0 {
+                            write_frame(&mut self.out, DATA, flags, id, &chunk);
+                        }
+                        stream.send_window -= chunk.len() as i64;
+                        self.send_window -= chunk.len() as i64;
+                        if !last {
+                            continue;
+                        }
+                        stream.local_closed |= *end_stream;
+                        if let Some(Outgoing::Data { sent, .. }) = stream.queue.pop_front() {
+                            let _ = sent.send(Ok(()));
+                        }
+                    }
+                    Outgoing::Trailers(_) => {
+                        let Some(Outgoing::Trailers(block)) = stream.queue.pop_front() else { unreachable!() };
+                        stream.local_closed = true;
+                        write_header_block(&mut self.out, self.max_frame_size, id, block, true);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fails one stream without affecting the rest of the connection.
+    fn reset_stream(&mut self, id: u32, reason: Reason) {
+        write_frame(&mut self.out, RST_STREAM, 0, id, &reason.code().to_be_bytes());
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.reset = true;
+            stream.send(Event::Error(Error::Http2(reason)));
+            stream.events = None;
+            for outgoing in stream.queue.drain(..) {
+                if let Outgoing::Data { sent, .. } = outgoing {
+                    let _ = sent.send(Err(Error::Http2(reason)));
+                }
+            }
+        }
+    }
+
+    /// Whether `id` names a stream this client could have opened by now.
+    fn is_known_stream(&self, id: u32) -> bool {
+        id % 2 == 1 && id < self.next_id
+    }
+
+    fn on_frame(&mut self, frame: RawFrame) -> Result<(), Error> {
+        if let Some((id, _, _)) = &self.continuation {
+            if frame.kind != CONTINUATION || frame.stream != *id {
+                return Err(Error::Http2(Reason::PROTOCOL_ERROR));
+            }
+        }
+        match frame.kind {
+            DATA => self.on_data(frame),
+            HEADERS => {
+                if frame.stream == 0 {
+                    return Err(Error::Http2(Reason::PROTOCOL_ERROR));
+                }
+                let mut block = strip_padding(frame.flags, frame.payload).map_err(Error::Http2)?;
+                if frame.flags & PRIORITY_FLAG != 0 {
+                    if block.len() < 5 {
+                        return Err(Error::Http2(Reason::PROTOCOL_ERROR));
+                    }
+                    block.advance(5);
+                }
+                let end_stream = frame.flags & END_STREAM != 0;
+                if frame.flags & END_HEADERS != 0 {
+                    self.on_header_block(frame.stream, &block, end_stream)
+                } else {
+                    self.continuation = Some((frame.stream, BytesMut::from(&block[..]), end_stream));
+                    Ok(())
+                }
+            }
+            CONTINUATION => {
+                let Some((id, mut block, end_stream)) = self.continuation.take() else {
+                    return Err(Error::Http2(Reason::PROTOCOL_ERROR));
+                };
+                block.extend_from_slice(&frame.payload);
+                if block.len() > MAX_HEADER_LIST_SIZE {
+                    return Err(Error::Http2(Reason::ENHANCE_YOUR_CALM));
+                }
+                if frame.flags & END_HEADERS != 0 {
+                    self.on_header_block(id, &block, end_stream)
+                } else {
+                    self.continuation = Some((id, block, end_stream));
+                    Ok(())
+                }
+            }
+            PRIORITY => match (frame.stream, frame.payload.len()) {
+                (0, _) => Err(Error::Http2(Reason::PROTOCOL_ERROR)),
+                (_, 5) => Ok(()),
+                (id, _) => {
+                    self.reset_stream(id, Reason::FRAME_SIZE_ERROR);
+                    Ok(())
+                }
+            },
+            RST_STREAM => {
+                if frame.stream == 0 {
+                    return Err(Error::Http2(Reason::PROTOCOL_ERROR));
+                }
+                if frame.payload.len() != 4 {
+                    return Err(Error::Http2(Reason::FRAME_SIZE_ERROR));
+                }
+                if !self.is_known_stream(frame.stream) {
+                    return Err(Error::Http2(Reason::PROTOCOL_ERROR));
+                }
+                let reason = Reason((&frame.payload[..]).get_u32());
+                if let Some(stream) = self.streams.get_mut(&frame.stream) {
+                    stream.reset = true;
+                    // A server may answer in full and then reset the rest of
+                    // the upload with NO_ERROR; the response still stands.
+                    if !(stream.remote_closed && reason == Reason::NO_ERROR) {
+                        stream.send(Event::Error(Error::Http2(reason)));
+                    }
+                    stream.events = None;
+                    for outgoing in stream.queue.drain(..) {
+                        if let Outgoing::Data { sent, .. } = outgoing {
+                            let _ = sent.send(Err(Error::Http2(reason)));
+                        }
+                    }
+                }
+                Ok(())
+            }
+            SETTINGS => self.on_settings(frame),
+            PUSH_PROMISE => Err(Error::Http2(Reason::PROTOCOL_ERROR)),
+            PING => {
+                if frame.stream != 0 {
+                    return Err(Error::Http2(Reason::PROTOCOL_ERROR));
+                }
+                if frame.payload.len() != 8 {
+                    return Err(Error::Http2(Reason::FRAME_SIZE_ERROR));
+                }
+                if frame.flags & ACK == 0 {
+                    write_frame(&mut self.out, PING, ACK, 0, &frame.payload);
+                }
+                Ok(())
+            }
+            GOAWAY => {
+                if frame.stream != 0 {
+                    return Err(Error::Http2(Reason::PROTOCOL_ERROR));
+                }
+                if frame.payload.len() < 8 {
+                    return Err(Error::Http2(Reason::FRAME_SIZE_ERROR));
+                }
+                let last_stream = (&frame.payload[..4]).get_u32() & 0x7fff_ffff;
+                self.going_away = true;
+                self.shared.closed.store(true, Ordering::Release);
+                // Streams the server never saw can go to another connection.
+                let refused: Vec<u32> = self.streams.keys().copied().filter(|&id| id > last_stream).collect();
+                for id in refused {
+                    if let Some(stream) = self.streams.remove(&id) {
+                        stream.send(Event::Error(Error::Http2(Reason::REFUSED_STREAM)));
+                        for outgoing in stream.queue {
+                            if let Outgoing::Data { sent, .. } = outgoing {
+                                let _ = sent.send(Err(Error::Http2(Reason::REFUSED_STREAM)));
+                            }
+                        }
+                    }
+                }
+                for pending in self.pending.drain(..) {
+                    let _ = pending.opened.send(Err(Error::Http2(Reason::REFUSED_STREAM)));
+                }
+                Ok(())
+            }
+            WINDOW_UPDATE => {
+                if frame.payload.len() != 4 {
+                    return Err(Error::Http2(Reason::FRAME_SIZE_ERROR));
+                }
+                let increment = ((&frame.payload[..]).get_u32() & 0x7fff_ffff) as i64;
+                if frame.stream == 0 {
+                    if increment == 0 {
+                        return Err(Error::Http2(Reason::PROTOCOL_ERROR));
+                    }
+                    self.send_window += increment;
+                    if self.send_window > MAX_WINDOW {
+                        return Err(Error::Http2(Reason::FLOW_CONTROL_ERROR));
+                    }
+                } else if let Some(stream) = self.streams.get_mut(&frame.stream) {
+                    stream.send_window += increment;
+                    if increment == 0 {
+                        self.reset_stream(frame.stream, Reason::PROTOCOL_ERROR);
+                    } else if stream.send_window > MAX_WINDOW {
+                        self.reset_stream(frame.stream, Reason::FLOW_CONTROL_ERROR);
+                    }
+                }
+                Ok(())
+            }
+            // Unknown frame types are ignored.
+            _ => Ok(()),
+        }
+    }
+
+    fn on_data(&mut self, frame: RawFrame) -> Result<(), Error> {
+        if frame.stream == 0 || !self.is_known_stream(frame.stream) {
+            return Err(Error::Http2(Reason::PROTOCOL_ERROR));
+        }
+        let flow_len = frame.payload.len() as i64;
+        if flow_len > self.recv_window {
+            return Err(Error::Http2(Reason::FLOW_CONTROL_ERROR));
+        }
+        self.recv_window -= flow_len;
+        let data = strip_padding(frame.flags, frame.payload).map_err(Error::Http2)?;
+        let padding = flow_len - data.len() as i64;
+
+        let Some(stream) = self.streams.get_mut(&frame.stream).filter(|s| !s.reset) else {
+            // Data for a stream we cancelled: nobody will read it.
+            self.release_connection(flow_len);
+            return Ok(());
+        };
+        if flow_len > stream.recv_window {
+            self.release_connection(flow_len);
+            self.reset_stream(frame.stream, Reason::FLOW_CONTROL_ERROR);
+            return Ok(());
+        }
+        if stream.remote_closed || !stream.head_received {
+            let reason = if stream.remote_closed { Reason::STREAM_CLOSED } else { Reason::PROTOCOL_ERROR };
+            self.release_connection(flow_len);
+            self.reset_stream(frame.stream, reason);
+            return Ok(());
+        }
+        stream.recv_window -= flow_len;
+        stream.recv_released += padding;
+        stream.received_length += data.len() as u64;
+        stream.buffered += data.len();
+        let overrun = stream.expected_length.is_some_and(|expected| stream.received_length > expected);
+        if !data.is_empty() {
+            stream.send(Event::Data(data));
+        }
+        self.release_connection(padding);
+        if overrun {
+            self.reset_stream(frame.stream, Reason::PROTOCOL_ERROR);
+        } else if frame.flags & END_STREAM != 0 {
+            self.end_remote(frame.stream);
+        }
+        Ok(())
+    }
+
+    fn on_header_block(&mut self, id: u32, block: &[u8], end_stream: bool) -> Result<(), Error> {
+        // Decode even for streams we no longer track to keep the HPACK state in sync.
+        let fields = self.decoder.decode(block, MAX_HEADER_LIST_SIZE).map_err(Error::Http2)?;
+        if !self.is_known_stream(id) {
+            return Err(Error::Http2(Reason::PROTOCOL_ERROR));
+        }
+        let Some(stream) = self.streams.get_mut(&id).filter(|s| !s.reset) else { return Ok(()) };
+        if stream.remote_closed {
+            self.reset_stream(id, Reason::STREAM_CLOSED);
+            return Ok(());
+        }
+
+        if stream.head_received {
+            // Anything after the final head is a trailer section, which must end the stream.
+            match decode_trailers(fields) {
+                Ok(trailers) if end_stream => {
+                    stream.send(Event::Trailers(trailers));
+                    self.end_remote(id);
+                }
+                _ => self.reset_stream(id, Reason::PROTOCOL_ERROR),
+            }
+            return Ok(());
+        }
+
+        let head = match decode_response(fields) {
+            Ok(head) => head,
+            Err(reason) => {
+                self.reset_stream(id, reason);
+                return Ok(());
+            }
+        };
+        if head.status.is_informational() {
+            // The final response follows on the same stream.
+            if end_stream {
+                self.reset_stream(id, Reason::PROTOCOL_ERROR);
+            } else {
+                stream.send(Event::Informational(InformationalResponse {
+                    status: head.status,
+                    headers: head.headers,
+                }));
+            }
+            return Ok(());
+        }
+        if has_no_body(&stream.method, head.status) {
+            // Content-Length describes the response to a GET here, not this one.
+            stream.expected_length = Some(0);
+        } else {
+            match content_length(&head.headers) {
+                Ok(length) => stream.expected_length = length,
+                Err(reason) => {
+                    self.reset_stream(id, reason);
+                    return Ok(());
+                }
+            }
+        }
+        stream.head_received = true;
+        stream.send(Event::Head(head));
+        if end_stream {
+            self.end_remote(id);
+        }
+        Ok(())
+    }
+
+    /// The server has sent END_STREAM; the response is complete.
+    fn end_remote(&mut self, id: u32) {
+        let Some(stream) = self.streams.get_mut(&id) else { return };
+        if stream.expected_length.is_some_and(|expected| expected != stream.received_length) {
+            self.reset_stream(id, Reason::PROTOCOL_ERROR);
+            return;
+        }
+        stream.remote_closed = true;
+        stream.events = None;
+    }
+
+    fn on_settings(&mut self, frame: RawFrame) -> Result<(), Error> {
+        if frame.stream != 0 {
+            return Err(Error::Http2(Reason::PROTOCOL_ERROR));
+        }
+        if frame.flags & ACK != 0 {
+            return if frame.payload.is_empty() { Ok(()) } else { Err(Error::Http2(Reason::FRAME_SIZE_ERROR)) };
+        }
+        if !frame.payload.len().is_multiple_of(6) {
+            return Err(Error::Http2(Reason::FRAME_SIZE_ERROR));
+        }
+        let mut payload = frame.payload;
+        while payload.has_remaining() {
+            let id = payload.get_u16();
+            let value = payload.get_u32();
+            match id {
+                // A server that allows no streams at all would leave requests
+                // queued until they time out; refuse them so they can go to
+                // another connection, and stop handing this one out.
+                SETTINGS_MAX_CONCURRENT_STREAMS if value == 0 => {
+                    self.max_concurrent = 0;
+                    self.going_away = true;
+                    self.shared.closed.store(true, Ordering::Release);
+                    for pending in self.pending.drain(..) {
+                        let _ = pending.opened.send(Err(Error::Http2(Reason::REFUSED_STREAM)));
+                    }
+                }
+                SETTINGS_MAX_CONCURRENT_STREAMS => self.max_concurrent = value as usize,
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value as i64 > MAX_WINDOW {
+                        return Err(Error::Http2(Reason::FLOW_CONTROL_ERROR));
+                    }
+                    let delta = value as i64 - self.initial_window;
+                    self.initial_window = value as i64;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                    }
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(DEFAULT_MAX_FRAME_SIZE..=(1 << 24) - 1).contains(&(value as usize)) {
+                        return Err(Error::Http2(Reason::PROTOCOL_ERROR));
+                    }
+                    self.max_frame_size = value as usize;
+                }
+                SETTINGS_ENABLE_PUSH if value > 1 => return Err(Error::Http2(Reason::PROTOCOL_ERROR)),
+                // The encoder uses no dynamic table, so SETTINGS_HEADER_TABLE_SIZE
+                // needs no action; SETTINGS_MAX_HEADER_LIST_SIZE is advisory.
+                _ => {}
+            }
+        }
+        write_frame(&mut self.out, SETTINGS, ACK, 0, &[]);
+        Ok(())
+    }
+}
+
+/// Sends `Cancel` when the caller lets go of a stream, so the connection
+/// task can reset it if needed and forget it.
+struct StreamRef {
+    id: u32,
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl Drop for StreamRef {
+    fn drop(&mut self) {
+        let _ = self.commands.send(Command::Cancel { id: self.id });
+    }
+}
+
+/// Waits for the next event on a stream within `limit` and the request deadline.
+async fn next_event(
+    events: &mut mpsc::UnboundedReceiver<Event>,
+    limit: Option<Duration>,
+    kind: TimeoutKind,
+    deadline: Option<Instant>,
+) -> Result<Option<Event>, Error> {
+    let next = timeout(limit, kind, async { Ok(events.recv().await) });
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, next).await.map_err(|_| Error::Timeout(TimeoutKind::Total))?,
+        None => next.await,
+    }
+}
+
+impl SendRequest {
+    /// Whether the connection stopped taking new requests.
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire) || self.commands.is_closed()
+    }
+
+    pub fn tls_info(&self) -> Option<Arc<TlsInfo>> {
+        self.tls_info.clone()
+    }
+
+    pub async fn send(
+        &self,
+        request: Request,
+        url: &Url,
+        limit: Option<u64>,
+        timeouts: &Timeouts,
+        deadline: Option<Instant>,
+    ) -> Result<(ResponseHead, ResponseBody), Error> {
+        let block = encode_request(&request, url)?;
+        let method = request.method.clone();
+        let body = request.body;
+        let end_stream = body.is_empty();
+        let (events_tx, mut events) = mpsc::unbounded_channel();
+        let (opened_tx, opened) = oneshot::channel();
+        let refused = || Error::Http2(Reason::REFUSED_STREAM);
+        self.commands
+            .send(Command::Open {
+                method: method.clone(),
+                block,
+                end_stream,
+                events: events_tx,
+                opened: opened_tx,
+            })
+            .map_err(|_| refused())?;
+        let id = opened.await.map_err(|_| refused())??;
+        let stream = StreamRef {
+            id,
+            commands: self.commands.clone(),
+        };
+
+        if !end_stream {
+            self.send_body(id, body, timeouts).await?;
+        }
+
+        let mut informational = Vec::new();
+        let mut head = loop {
+            match next_event(&mut events, timeouts.first_byte, TimeoutKind::FirstByte, deadline).await? {
+                Some(Event::Informational(response)) => informational.push(response),
+                Some(Event::Head(head)) => break head,
+                Some(Event::Error(err)) => return Err(err),
+                Some(_) => return Err(Error::Http2(Reason::PROTOCOL_ERROR)),
+                None => return Err(Error::UnexpectedEof),
+            }
+        };
+        head.informational = informational;
+        if let (Some(limit), Ok(Some(length))) = (limit, content_length(&head.headers)) {
+            if length > limit && !has_no_body(&method, head.status) {
+                return Err(Error::BodyTooLarge { limit });
+            }
+        }
+
+        let read_idle = timeouts.read_idle;
+        let state = (events, stream, 0u64);
+        let body = ResponseBody::from_frames(stream::try_unfold(state, move |(mut events, stream, read)| async move {
+            match next_event(&mut events, read_idle, TimeoutKind::ReadIdle, deadline).await? {
+                Some(Event::Data(data)) => {
+                    let read = read + data.len() as u64;
+                    if limit.is_some_and(|limit| read > limit) {
+                        return Err(Error::BodyTooLarge { limit: limit.unwrap_or_default() });
+                    }
+                    let _ = stream.commands.send(Command::Release { id: stream.id, len: data.len() });
+                    Ok(Some((Frame::Data(data), (events, stream, read))))
+                }
+                Some(Event::Trailers(trailers)) => Ok(Some((Frame::Trailers(trailers), (events, stream, read)))),
+                Some(Event::Error(err)) => Err(err),
+                Some(Event::Informational(_) | Event::Head(_)) => Err(Error::Http2(Reason::PROTOCOL_ERROR)),
+                None => Ok(None),
+            }
+        }));
+        Ok((head, body))
+    }
+
+    /// Streams the request body out. Each chunk is handed over only once the
+    /// previous one has gone out, which keeps flow control end to end.
+    async fn send_body(&self, id: u32, body: Body, timeouts: &Timeouts) -> Result<(), Error> {
+        let known_length = body.len().is_some();
+        let trailers = body.trailers().filter(|t| !t.is_empty()).cloned();
+        let (mut chunks, _) = body.into_parts();
+        let mut ended = false;
+        while let Some(chunk) = chunks.next().await {
+            let end_stream = known_length && trailers.is_none();
+            ended = end_stream;
+            if !self.send_data(id, chunk?, end_stream, timeouts).await? {
+                // The server has reset the upload; it may still have answered.
+                return Ok(());
+            }
+        }
+        match trailers {
+            Some(trailers) => {
+                let mut block = BytesMut::new();
+                for (name, value) in &trailers {
+                    hpack::encode_field(&mut block, name.as_str().as_bytes(), value.as_bytes(), value.is_sensitive());
+                }
+                let _ = self.commands.send(Command::Trailers { id, block: block.freeze() });
+            }
+            None if !ended => {
+                self.send_data(id, Bytes::new(), true, timeouts).await?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Queues one DATA chunk and waits until it is written. Returns `false`
+    /// if the stream was closed before it could be sent.
+    async fn send_data(&self, id: u32, data: Bytes, end_stream: bool, timeouts: &Timeouts) -> Result<bool, Error> {
+        let (sent_tx, sent) = oneshot::channel();
+        let command = Command::Data {
+            id,
+            data,
+            end_stream,
+            sent: sent_tx,
+        };
+        if self.commands.send(command).is_err() {
+            return Err(Error::Http2(Reason::CANCEL));
+        }
+        let sent = timeout(timeouts.read_idle, TimeoutKind::ReadIdle, async {
+            sent.await.map_err(|_| Error::Http2(Reason::CANCEL))
+        })
+        .await?;
+        Ok(sent.is_ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_frames_only_once_complete() {
+        let mut out = BytesMut::new();
+        write_frame(&mut out, PING, ACK, 0, b"12345678");
+        let mut buf = BytesMut::from(&out[..10]);
+        assert!(parse_frame(&mut buf, DEFAULT_MAX_FRAME_SIZE).unwrap().is_none());
+        buf.extend_from_slice(&out[10..]);
+        let frame = parse_frame(&mut buf, DEFAULT_MAX_FRAME_SIZE).unwrap().unwrap();
+        assert_eq!((frame.kind, frame.flags, frame.stream), (PING, ACK, 0));
+        assert_eq!(frame.payload, "12345678");
+        assert!(buf.is_empty());
+
+        let mut big = BytesMut::new();
+        write_frame_head(&mut big, DEFAULT_MAX_FRAME_SIZE + 1, DATA, 0, 1);
+        assert_eq!(parse_frame(&mut big, DEFAULT_MAX_FRAME_SIZE).err(), Some(Reason::FRAME_SIZE_ERROR));
+    }
+
+    #[test]
+    fn rejects_malformed_response_heads() {
+        let field = |n: &'static str, v: &'static str| (Bytes::from(n), Bytes::from(v));
+        let head = decode_response(vec![field(":status", "204"), field("x-a", "1")]).unwrap();
+        assert_eq!(head.status, StatusCode::NO_CONTENT);
+        assert_eq!(head.version, Version::HTTP_2);
+
+        assert!(decode_response(vec![field("x-a", "1"), field(":status", "200")]).is_err());
+        assert!(decode_response(vec![field(":status", "200"), field("connection", "close")]).is_err());
+        assert!(decode_response(vec![field(":status", "200"), field("X-Upper", "1")]).is_err());
+        assert!(decode_response(vec![field(":path", "/")]).is_err());
+    }
+}
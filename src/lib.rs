@@ -1,31 +1,108 @@
 use std::fmt;
 use std::fmt::{Display, Formatter};
-use http::{HeaderMap, Method};
+use std::io;
+use std::sync::Arc;
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, Method, Version};
 use url::Url;
 
+pub use body::{Body, ResponseBody};
+pub use http1::OriginalHeaders;
+pub use request::RequestBuilder;
+pub use timeout::{TimeoutKind, Timeouts};
+pub use tls::TlsInfo;
+
+pub mod body;
+mod chunked;
 pub mod client;
+pub mod cookie;
+mod decompress;
+mod hpack;
+mod http1;
+pub mod http2;
+pub mod multipart;
+mod pool;
+pub mod proxy;
+pub mod redirect;
+pub mod request;
+mod timeout;
+pub mod tls;
+pub mod transport;
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct Request {
     pub method: Method,
     pub uri: Url,
-    pub version: String,
+    /// Informational: HTTP/1.1 is written on plain connections and HTTP/2
+    /// when the server negotiates it.
+    pub version: Version,
     pub headers: HeaderMap,
-    pub body: Vec<u8>,
+    pub original_headers: OriginalHeaders,
+    pub body: Body,
+    /// Overrides for the client's default timeouts; unset fields inherit them.
+    pub timeouts: Timeouts,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct Response {
+    pub version: Version,
     pub status_code: u16,
     pub status: http::StatusCode,
     pub reason_phrase: String,
     pub headers: HeaderMap,
-    pub body: Vec<u8>,
+    pub body: ResponseBody,
+    raw_headers: Vec<(String, HeaderValue)>,
+    url: Option<Url>,
+    redirects: Vec<Url>,
+    informational: Vec<InformationalResponse>,
+    tls_info: Option<Arc<TlsInfo>>,
+}
+
+/// An interim 1xx response, such as 103 Early Hints, received before the
+/// final response.
+#[derive(Clone, Debug)]
+pub struct InformationalResponse {
+    pub status: http::StatusCode,
+    pub headers: HeaderMap,
 }
 
 #[derive(Debug)]
-pub struct Error {
-    message: String,
+#[non_exhaustive]
+pub enum Error {
+    /// The URL has no host, an unsupported scheme, or a host that is not a valid DNS name.
+    InvalidUrl(String),
+    Dns { host: String, source: io::Error },
+    Connect { host: String, port: u16, source: io::Error },
+    TlsHandshake(io::Error),
+    /// The server's certificate verified, but its key is none of the pins
+    /// configured for `host`.
+    CertificatePin { host: String },
+    Timeout(TimeoutKind),
+    Io(io::Error),
+    MalformedStatusLine(String),
+    InvalidHeader(String),
+    InvalidChunk(&'static str),
+    BodyTooLarge { limit: u64 },
+    UnexpectedEof,
+    RedirectLoop(Url),
+    TooManyRedirects(usize),
+    /// The HTTP/2 peer reset the stream or the connection, or broke the protocol.
+    Http2(http2::Reason),
+    /// The TLS settings given to `ClientBuilder` are unusable.
+    TlsConfig(String),
+    /// The proxy answered `CONNECT` with this status instead of opening a
+    /// tunnel; 407 means it wants (other) credentials.
+    ProxyConnect(http::StatusCode),
+    /// The SOCKS5 proxy refused the connection or broke the protocol.
+    Socks(&'static str),
+    /// The response body did not match its `Content-Encoding`.
+    Decode(io::Error),
+    /// A request body could not be serialized to JSON.
+    #[cfg(feature = "json")]
+    JsonEncode(serde_json::Error),
+    /// A body was not the expected JSON. `snippet` holds its first bytes.
+    #[cfg(feature = "json")]
+    Json { status: Option<http::StatusCode>, snippet: String, source: serde_json::Error },
 }
 
 impl Default for Request {
@@ -33,29 +110,114 @@ impl Default for Request {
         Self {
             method: Default::default(),
             uri: Url::parse("http://localhost").unwrap(),
-            version: "".to_string(),
+            version: Version::HTTP_11,
             headers: Default::default(),
-            body: vec![],
+            original_headers: Default::default(),
+            body: Body::empty(),
+            timeouts: Default::default(),
         }
     }
 }
 
+impl Request {
+    /// Clones the request, unless its body is a stream that can only be sent once.
+    pub fn try_clone(&self) -> Option<Request> {
+        let body = self.body.try_clone()?;
+        Some(Request {
+            body,
+            ..self.clone_without_body()
+        })
+    }
+
+    pub(crate) fn clone_without_body(&self) -> Request {
+        Request {
+            method: self.method.clone(),
+            uri: self.uri.clone(),
+            version: self.version,
+            headers: self.headers.clone(),
+            original_headers: self.original_headers.clone(),
+            body: Body::empty(),
+            timeouts: self.timeouts,
+        }
+    }
+
+    /// Appends a header, remembering the exact spelling of `name` for the wire.
+    pub fn append_header(&mut self, name: &str, value: HeaderValue) -> Result<(), Error> {
+        let name = self.original_headers.record(name)?;
+        self.headers.append(name, value);
+        Ok(())
+    }
+}
+
+impl Response {
+    /// Reads the next piece of the body, or `None` at its end.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        self.body.chunk().await
+    }
+
+    pub async fn bytes(self) -> Result<Bytes, Error> {
+        self.body.bytes().await
+    }
+
+    pub async fn text(self) -> Result<String, Error> {
+        self.body.text().await
+    }
+
+    #[cfg(feature = "json")]
+    pub async fn json<T: serde::de::DeserializeOwned>(self) -> Result<T, Error> {
+        let status = self.status;
+        let bytes = self.body.bytes().await?;
+        body::from_json(Some(status), &bytes)
+    }
+
+    /// Trailer fields, available once the whole body has been read.
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        self.body.trailers()
+    }
+
+    /// Header fields exactly as received: original name casing, wire order,
+    /// and one entry per field line.
+    pub fn headers_raw(&self) -> &[(String, HeaderValue)] {
+        &self.raw_headers
+    }
+
+    /// The URL that produced this response, after any redirects.
+    pub fn url(&self) -> Option<&Url> {
+        self.url.as_ref()
+    }
+
+    /// Interim 1xx responses the server sent before this one, oldest first.
+    pub fn informational(&self) -> &[InformationalResponse] {
+        &self.informational
+    }
+
+    /// The TLS session the response arrived over; `None` for plain HTTP.
+    pub fn tls_info(&self) -> Option<&TlsInfo> {
+        self.tls_info.as_deref()
+    }
+
+    /// Every URL that answered with a redirect on the way here, oldest first.
+    pub fn redirects(&self) -> &[Url] {
+        &self.redirects
+    }
+}
+
 impl Display for Request {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "{} {} {}\n{:?}{}",
+            "{} {} {:?}\n{:?}{}",
             self.method,
             self.uri,
             self.version,
             self.headers,
-            if !self.body.is_empty() {
-                format!(
+            match self.body.as_bytes() {
+                Some(body) if !body.is_empty() => format!(
                     "\nBody:\n{}",
-                    String::from_utf8_lossy(&self.body)
-                )
-            } else {
-                String::new()
+                    String::from_utf8_lossy(body)
+                ),
+                Some(_) => String::new(),
+                None => "\nBody: <stream>".to_string(),
             }
         )
     }
@@ -70,27 +232,120 @@ impl Display for Response {
             self.reason_phrase,
             self.status,
             self.headers,
-            if !self.body.is_empty() {
-                format!(
+            match self.body.as_bytes() {
+                Some(body) if !body.is_empty() => format!(
                     "\nBody:\n{}",
-                    String::from_utf8_lossy(&self.body)
-                )
-            } else {
-                String::new()
+                    String::from_utf8_lossy(body)
+                ),
+                Some(_) => String::new(),
+                None => "\nBody: <stream>".to_string(),
+            }
+        )
+    }
+}
+
+impl Error {
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            Error::Timeout(_) => true,
+            Error::Io(err) | Error::Connect { source: err, .. } | Error::TlsHandshake(err) => {
+                err.kind() == io::ErrorKind::TimedOut
             }
+            _ => false,
+        }
+    }
+
+    /// Whether the request failed before a connection to the server was established.
+    pub fn is_connect(&self) -> bool {
+        matches!(
+            self,
+            Error::Dns { .. } | Error::Connect { .. } | Error::TlsHandshake(_) | Error::CertificatePin { .. } | Error::ProxyConnect(_) | Error::Socks(_)
         )
     }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "Error: {}", self.message)
+        match self {
+            Error::InvalidUrl(reason) => write!(f, "invalid URL: {}", reason),
+            Error::Dns { host, .. } => write!(f, "failed to resolve {}", host),
+            Error::Connect { host, port, .. } => write!(f, "failed to connect to {}:{}", host, port),
+            Error::TlsHandshake(_) => write!(f, "TLS handshake failed"),
+            Error::CertificatePin { host } => write!(f, "certificate for {} does not match any pinned key", host),
+            Error::Timeout(kind) => write!(f, "{} timeout elapsed", kind),
+            Error::Io(_) => write!(f, "I/O error"),
+            Error::MalformedStatusLine(line) => write!(f, "malformed status line: {:?}", line),
+            Error::InvalidHeader(line) => write!(f, "invalid header: {:?}", line),
+            Error::InvalidChunk(reason) => write!(f, "invalid chunked body: {}", reason),
+            Error::BodyTooLarge { limit } => write!(f, "body exceeds limit of {} bytes", limit),
+            Error::UnexpectedEof => write!(f, "connection closed before message completed"),
+            Error::RedirectLoop(url) => write!(f, "redirect loop detected at {}", url),
+            Error::TooManyRedirects(max) => write!(f, "more than {} redirects", max),
+            Error::Http2(reason) => write!(f, "HTTP/2 error: {}", reason),
+            Error::TlsConfig(reason) => write!(f, "invalid TLS configuration: {}", reason),
+            Error::ProxyConnect(status) => write!(f, "proxy refused to open a tunnel: {}", status),
+            Error::Socks(reason) => write!(f, "SOCKS5 proxy error: {}", reason),
+            Error::Decode(_) => write!(f, "failed to decode response body"),
+            #[cfg(feature = "json")]
+            Error::JsonEncode(_) => write!(f, "failed to encode JSON body"),
+            #[cfg(feature = "json")]
+            Error::Json { status: Some(status), snippet, .. } => {
+                write!(f, "failed to decode JSON body of {} response: {:?}", status, snippet)
+            }
+            #[cfg(feature = "json")]
+            Error::Json { status: None, snippet, .. } => write!(f, "failed to decode JSON body: {:?}", snippet),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Dns { source, .. } | Error::Connect { source, .. } => Some(source),
+            Error::TlsHandshake(err) | Error::Io(err) | Error::Decode(err) => Some(err),
+            #[cfg(feature = "json")]
+            Error::JsonEncode(source) | Error::Json { source, .. } => Some(source),
+            _ => None,
+        }
     }
 }
 
-impl std::error::Error for Error {}
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        let kind = err.get_ref().and_then(|inner| inner.downcast_ref::<TimeoutKind>()).copied();
+        match kind {
+            Some(kind) if err.kind() == io::ErrorKind::TimedOut => Error::Timeout(kind),
+            _ => Error::Io(err),
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn json_error_reports_status_and_truncated_body() {
+        use super::*;
 
+        let body = format!("<html>{}</html>", "x".repeat(1000));
+        let response = Response {
+            version: Version::HTTP_11,
+            status_code: 502,
+            status: http::StatusCode::BAD_GATEWAY,
+            reason_phrase: "Bad Gateway".to_string(),
+            headers: HeaderMap::new(),
+            body: ResponseBody::from(body.into_bytes()),
+            raw_headers: Vec::new(),
+            url: None,
+            redirects: Vec::new(),
+            informational: Vec::new(),
+            tls_info: None,
+        };
+        let err = response.json::<serde_json::Value>().await.unwrap_err();
+        let Error::Json { status, snippet, .. } = &err else { panic!("unexpected error: {:?}", err) };
+        assert_eq!(*status, Some(http::StatusCode::BAD_GATEWAY));
+        assert!(snippet.starts_with("<html>xxx") && snippet.ends_with("..."));
+        assert!(snippet.len() < 300);
+        assert!(err.to_string().contains("502 Bad Gateway"));
+    }
 }
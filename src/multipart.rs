@@ -0,0 +1,253 @@
+//! `multipart/form-data` bodies (RFC 7578), streamed part by part.
+
+use crate::{Body, Error};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::stream::{self, StreamExt};
+use http::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use tokio::io::AsyncRead;
+
+/// A set of named parts, sent with `RequestBuilder::multipart`.
+///
+/// Part bodies are not buffered: files and readers are streamed into the
+/// request as it is written, so the request uses chunked encoding. A form
+/// whose parts are all in memory is sent whole, with `Content-Length`, and
+/// can be sent again on a redirect or retry.
+#[derive(Debug)]
+pub struct Form {
+    boundary: String,
+    parts: Vec<(String, Part)>,
+}
+
+/// One field of a `Form`: a body plus its own headers.
+#[derive(Debug)]
+pub struct Part {
+    body: Body,
+    file_name: Option<String>,
+    content_type: Option<HeaderValue>,
+    headers: HeaderMap,
+}
+
+impl Form {
+    pub fn new() -> Self {
+        Form {
+            boundary: generate_boundary(),
+            parts: Vec::new(),
+        }
+    }
+
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// The `Content-Type` value announcing this form's boundary.
+    pub fn content_type(&self) -> HeaderValue {
+        HeaderValue::try_from(format!("multipart/form-data; boundary={}", self.boundary))
+            .expect("boundary is a valid header value")
+    }
+
+    pub fn text<N: Into<String>, V: Into<String>>(self, name: N, value: V) -> Self {
+        self.part(name, Part::text(value))
+    }
+
+    /// Adds the file at `path`, named after its last path component.
+    pub async fn file<N: Into<String>, P: AsRef<Path>>(self, name: N, path: P) -> Result<Self, Error> {
+        Ok(self.part(name, Part::file(path).await?))
+    }
+
+    pub fn part<N: Into<String>>(mut self, name: N, part: Part) -> Self {
+        self.parts.push((name.into(), part));
+        self
+    }
+
+    pub(crate) fn into_body(self) -> Body {
+        if self.parts.iter().all(|(_, part)| part.body.as_bytes().is_some()) {
+            let mut body = BytesMut::new();
+            for (name, part) in &self.parts {
+                body.put(part.head(&self.boundary, name));
+                body.put_slice(part.body.as_bytes().unwrap_or_default());
+                body.put_slice(b"\r\n");
+            }
+            body.put_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+            return Body::from(body.freeze());
+        }
+        let boundary = self.boundary;
+        let closing = Bytes::from(format!("--{}--\r\n", boundary));
+        let parts = stream::iter(self.parts).flat_map(move |(name, part)| {
+            let head = part.head(&boundary, &name);
+            let (body, _) = part.body.into_parts();
+            stream::once(async move { Ok(head) })
+                .chain(body)
+                .chain(stream::once(async { Ok(Bytes::from_static(b"\r\n")) }))
+        });
+        Body::from_stream(parts.chain(stream::once(async move { Ok(closing) })))
+    }
+}
+
+impl Default for Form {
+    fn default() -> Self {
+        Form::new()
+    }
+}
+
+impl Part {
+    pub fn text<V: Into<String>>(value: V) -> Self {
+        Part::new(Body::from(value.into()))
+    }
+
+    pub fn bytes<B: Into<Bytes>>(bytes: B) -> Self {
+        Part::new(Body::from(bytes.into()))
+    }
+
+    pub fn reader<R: AsyncRead + Send + 'static>(reader: R) -> Self {
+        Part::new(Body::from_reader(reader))
+    }
+
+    /// Opens `path` now and streams it when the request is sent. The part is
+    /// `application/octet-stream` named after the file until told otherwise.
+    pub async fn file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file = tokio::fs::File::open(path).await?;
+        let mut part = Part::reader(file).content_type(HeaderValue::from_static("application/octet-stream"));
+        part.file_name = path.file_name().map(|name| name.to_string_lossy().into_owned());
+        Ok(part)
+    }
+
+    fn new(body: Body) -> Self {
+        Part {
+            body,
+            file_name: None,
+            content_type: None,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    pub fn file_name<N: Into<String>>(mut self, name: N) -> Self {
+        self.file_name = Some(name.into());
+        self
+    }
+
+    pub fn content_type(mut self, value: HeaderValue) -> Self {
+        self.content_type = Some(value);
+        self
+    }
+
+    /// Adds a header to this part; `Content-Disposition` and `Content-Type`
+    /// are written from the part's name, file name and content type instead.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    fn head(&self, boundary: &str, name: &str) -> Bytes {
+        let mut head = BytesMut::new();
+        head.put_slice(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"", boundary, escape(name)).as_bytes());
+        if let Some(file_name) = &self.file_name {
+            head.put_slice(format!("; filename=\"{}\"", escape(file_name)).as_bytes());
+        }
+        head.put_slice(b"\r\n");
+        if let Some(content_type) = &self.content_type {
+            head.put_slice(b"Content-Type: ");
+            head.put_slice(content_type.as_bytes());
+            head.put_slice(b"\r\n");
+        }
+        for (name, value) in &self.headers {
+            if name == http::header::CONTENT_DISPOSITION || name == http::header::CONTENT_TYPE {
+                continue;
+            }
+            head.put_slice(name.as_str().as_bytes());
+            head.put_slice(b": ");
+            head.put_slice(value.as_bytes());
+            head.put_slice(b"\r\n");
+        }
+        head.put_slice(b"\r\n");
+        head.freeze()
+    }
+}
+
+/// Percent-encodes the characters that would end a quoted parameter, as the
+/// HTML form submission algorithm does.
+fn escape(value: &str) -> String {
+    value.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+fn generate_boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let state = RandomState::new();
+    let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let a = state.hash_one((nanos, COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let b = state.hash_one(a);
+    format!("kusari-{:016x}{:016x}", a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn collect(body: Body) -> String {
+        let (mut stream, _) = body.into_parts();
+        let mut out = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            out.extend_from_slice(&chunk.unwrap());
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[tokio::test]
+    async fn streams_parts_with_headers_and_closing_boundary() {
+        let form = Form::new()
+            .text("title", "hello")
+            .part(
+                "upload",
+                Part::reader(&b"file contents"[..])
+                    .file_name("a\"b.txt")
+                    .content_type(HeaderValue::from_static("text/plain"))
+                    .header(HeaderName::from_static("x-part"), HeaderValue::from_static("1")),
+            );
+        let boundary = form.boundary().to_string();
+        assert_eq!(form.content_type(), format!("multipart/form-data; boundary={}", boundary).as_str());
+        let body = form.into_body();
+        assert!(body.is_chunked());
+
+        let expected = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhello\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"a%22b.txt\"\r\n\
+             Content-Type: text/plain\r\nx-part: 1\r\n\r\nfile contents\r\n--{b}--\r\n",
+            b = boundary
+        );
+        assert_eq!(collect(body).await, expected);
+    }
+
+    #[tokio::test]
+    async fn in_memory_forms_have_a_length() {
+        let form = Form::new().text("a", "1").part("b", Part::bytes(&b"\x00"[..]).file_name("b.bin"));
+        let boundary = form.boundary().to_string();
+        let body = form.into_body();
+        assert!(!body.is_chunked());
+        let expected = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"b\"; filename=\"b.bin\"\r\n\r\n\x00\r\n--{b}--\r\n",
+            b = boundary
+        );
+        assert_eq!(body.len(), Some(expected.len() as u64));
+        assert_eq!(collect(body.try_clone().unwrap()).await, expected);
+    }
+
+    #[tokio::test]
+    async fn file_parts_are_named_after_the_file() {
+        let path = std::env::temp_dir().join(format!("kusari-multipart-{}.bin", std::process::id()));
+        tokio::fs::write(&path, b"\x00\x01\x02").await.unwrap();
+        let form = Form::new().file("data", &path).await.unwrap();
+        let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
+        let body = collect(form.into_body()).await;
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert!(body.contains(&format!("name=\"data\"; filename=\"{}\"\r\n", file_name)));
+        assert!(body.contains("Content-Type: application/octet-stream\r\n\r\n\x00\x01\x02\r\n"));
+        assert_ne!(Form::new().boundary(), Form::new().boundary());
+    }
+}
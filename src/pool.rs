@@ -0,0 +1,266 @@
+use crate::http2::SendRequest;
+use crate::proxy::Via;
+use crate::transport::Transport;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard, OwnedSemaphorePermit, Semaphore};
+use url::Url;
+
+/// Connections are shared only between requests to the same origin.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct PoolKey {
+    pub scheme: String,
+    pub host: String,
+    pub port: u16,
+    /// The proxy the connection goes through, if any.
+    pub proxy: Option<Via>,
+}
+
+impl PoolKey {
+    /// IPv6 hosts are stored without the brackets a URL puts around them,
+    /// ready for address resolution.
+    pub fn from_url(url: &Url) -> Option<PoolKey> {
+        let host = match url.host()? {
+            url::Host::Ipv6(ip) => ip.to_string(),
+            host => host.to_string(),
+        };
+        Some(PoolKey {
+            scheme: url.scheme().to_string(),
+            host,
+            port: url.port_or_known_default()?,
+            proxy: None,
+        })
+    }
+}
+
+/// Keep-alive connections waiting to be reused, plus a per-origin cap on
+/// how many connections may be open at once.
+///
+/// HTTP/2 connections are not checked out at all: one is shared by every
+/// request to its origin for as long as it stays open.
+pub(crate) struct Pool {
+    idle_timeout: Option<Duration>,
+    max_per_host: usize,
+    inner: Mutex<PoolInner>,
+}
+
+#[derive(Default)]
+struct PoolInner {
+    idle: HashMap<PoolKey, Vec<Idle>>,
+    limits: HashMap<PoolKey, Arc<Semaphore>>,
+    http2: HashMap<PoolKey, SendRequest>,
+    /// Origins that answered a fresh connection with HTTP/1.1.
+    http1: HashSet<PoolKey>,
+    dialing: HashMap<PoolKey, Arc<AsyncMutex<()>>>,
+}
+
+struct Idle {
+    transport: Transport,
+    since: Instant,
+}
+
+impl Pool {
+    pub fn new(idle_timeout: Option<Duration>, max_per_host: usize) -> Self {
+        Pool {
+            idle_timeout,
+            max_per_host: max_per_host.min(Semaphore::MAX_PERMITS),
+            inner: Mutex::new(PoolInner::default()),
+        }
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// The open HTTP/2 connection to `key`, if there is one.
+    pub fn http2(&self, key: &PoolKey) -> Option<SendRequest> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.http2.get(key) {
+            Some(conn) if !conn.is_closed() => Some(conn.clone()),
+            Some(_) => {
+                inner.http2.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn put_http2(&self, key: PoolKey, conn: SendRequest) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.http1.remove(&key);
+        inner.http2.insert(key, conn);
+    }
+
+    pub fn set_http1(&self, key: &PoolKey) {
+        self.inner.lock().unwrap().http1.insert(key.clone());
+    }
+
+    pub fn is_http1(&self, key: &PoolKey) -> bool {
+        self.inner.lock().unwrap().http1.contains(key)
+    }
+
+    /// Lets one request at a time open a connection to `key` until the
+    /// protocol it speaks is known, so that concurrent first requests to an
+    /// HTTP/2 server end up sharing a single connection. Origins known to use
+    /// HTTP/1.1 are dialed in parallel.
+    pub async fn dial_lock(&self, key: &PoolKey) -> Option<OwnedMutexGuard<()>> {
+        let lock = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.http1.contains(key) {
+                return None;
+            }
+            inner.dialing.entry(key.clone()).or_default().clone()
+        };
+        Some(lock.lock_owned().await)
+    }
+
+    /// Waits until `key` is below its connection cap, then hands out the most
+    /// recently used idle connection if there is one and `reuse` is set.
+    pub async fn checkout(self: &Arc<Self>, key: &PoolKey, reuse: bool) -> (OwnedSemaphorePermit, Option<Transport>) {
+        let limit = {
+            let mut inner = self.inner.lock().unwrap();
+            inner
+                .limits
+                .entry(key.clone())
+                .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_host)))
+                .clone()
+        };
+        let permit = limit.acquire_owned().await.expect("pool semaphore is never closed");
+
+        let mut inner = self.inner.lock().unwrap();
+        self.evict_expired(&mut inner);
+        forget_unused(&mut inner);
+        let transport = if reuse {
+            inner.idle.get_mut(key).and_then(|idle| idle.pop()).map(|idle| idle.transport)
+        } else {
+            None
+        };
+        (permit, transport)
+    }
+
+    pub fn put(&self, key: PoolKey, transport: Transport) {
+        if self.idle_timeout == Some(Duration::ZERO) {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        self.evict_expired(&mut inner);
+        forget_unused(&mut inner);
+        inner.idle.entry(key).or_default().push(Idle {
+            transport,
+            since: Instant::now(),
+        });
+    }
+
+    fn evict_expired(&self, inner: &mut PoolInner) {
+        let Some(timeout) = self.idle_timeout else { return };
+        inner.idle.retain(|_, idle| {
+            idle.retain(|conn| conn.since.elapsed() < timeout);
+            !idle.is_empty()
+        });
+    }
+}
+
+/// Drops the per-origin state of origins with no idle, active or dialing
+/// connections, so that a client talking to many hosts does not keep them all.
+/// Every checked-out permit and pending dial holds a clone of its `Arc`.
+fn forget_unused(inner: &mut PoolInner) {
+    let PoolInner {
+        idle,
+        limits,
+        http2,
+        dialing,
+        ..
+    } = inner;
+    limits.retain(|key, limit| Arc::strong_count(limit) > 1 || idle.contains_key(key));
+    dialing.retain(|_, lock| Arc::strong_count(lock) > 1);
+    http2.retain(|_, conn| !conn.is_closed());
+}
+
+/// A connection checked out of the pool. It only goes back to the pool when
+/// explicitly released after a complete keep-alive exchange; dropping it
+/// closes the connection.
+pub(crate) struct Pooled {
+    pool: Arc<Pool>,
+    key: PoolKey,
+    transport: Transport,
+    reused: bool,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Pooled {
+    pub fn new(pool: Arc<Pool>, key: PoolKey, transport: Transport, reused: bool, permit: OwnedSemaphorePermit) -> Self {
+        Pooled {
+            pool,
+            key,
+            transport,
+            reused,
+            _permit: permit,
+        }
+    }
+
+    pub fn is_reused(&self) -> bool {
+        self.reused
+    }
+
+    pub fn key(&self) -> &PoolKey {
+        &self.key
+    }
+
+    pub fn transport(&self) -> &Transport {
+        &self.transport
+    }
+
+    pub fn release(self) {
+        self.pool.put(self.key, self.transport);
+    }
+}
+
+impl AsyncRead for Pooled {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().transport).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Pooled {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().transport).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().transport).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().transport).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn forgets_origins_without_connections() {
+        let pool = Arc::new(Pool::new(None, 4));
+        let key = |url: &str| PoolKey::from_url(&Url::parse(url).unwrap()).unwrap();
+        let (a, b) = (key("http://a.test/"), key("http://b.test/"));
+        let origins = |pool: &Pool| {
+            let inner = pool.inner.lock().unwrap();
+            (inner.limits.len(), inner.dialing.len())
+        };
+
+        let dialing = pool.dial_lock(&a).await;
+        let (permit, _) = pool.checkout(&a, true).await;
+        let _ = pool.checkout(&b, true).await;
+        assert_eq!(origins(&pool), (2, 1));
+
+        drop((dialing, permit));
+        let _ = pool.checkout(&b, true).await;
+        assert_eq!(origins(&pool), (1, 0));
+    }
+}
@@ -0,0 +1,149 @@
+use http::header::{
+    AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST, PROXY_AUTHORIZATION, TRANSFER_ENCODING,
+};
+use http::{HeaderMap, Method, StatusCode};
+use std::fmt;
+use std::sync::Arc;
+use url::Url;
+
+/// How many redirects `Policy::default()` follows; also the hard cap for `Policy::Custom`.
+pub(crate) const MAX_REDIRECTS: usize = 10;
+
+/// Decides whether the client follows `3xx` responses.
+#[derive(Clone)]
+pub enum Policy {
+    /// Return every redirect response to the caller as-is.
+    None,
+    /// Follow up to this many redirects, then fail with `Error::TooManyRedirects`.
+    Limited(usize),
+    /// Ask the closure about every redirect, up to the default limit of 10.
+    Custom(Arc<dyn Fn(&Attempt<'_>) -> Action + Send + Sync>),
+}
+
+impl Policy {
+    pub fn custom<F>(policy: F) -> Policy
+    where
+        F: Fn(&Attempt<'_>) -> Action + Send + Sync + 'static,
+    {
+        Policy::Custom(Arc::new(policy))
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::Limited(MAX_REDIRECTS)
+    }
+}
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Policy::None => f.write_str("None"),
+            Policy::Limited(max) => f.debug_tuple("Limited").field(max).finish(),
+            Policy::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// A redirect about to be followed, as seen by a `Policy::Custom` closure.
+pub struct Attempt<'a> {
+    pub(crate) status: StatusCode,
+    pub(crate) next: &'a Url,
+    pub(crate) previous: &'a [Url],
+}
+
+impl Attempt<'_> {
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn url(&self) -> &Url {
+        self.next
+    }
+
+    /// Every URL that has answered with a redirect so far, oldest first.
+    pub fn previous(&self) -> &[Url] {
+        self.previous
+    }
+
+    pub fn follow(&self) -> Action {
+        Action::Follow
+    }
+
+    pub fn stop(&self) -> Action {
+        Action::Stop
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Follow,
+    /// Hand the redirect response back to the caller.
+    Stop,
+}
+
+pub(crate) fn is_redirect(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308)
+}
+
+/// The method to use for the next hop, and whether the body goes along.
+///
+/// 307 and 308 repeat the request unchanged. 303 turns everything but `HEAD`
+/// into a bodyless `GET`, and 301/302 do the same for `POST` as browsers do.
+pub(crate) fn next_method(status: StatusCode, method: &Method) -> (Method, bool) {
+    match status.as_u16() {
+        303 if method != Method::HEAD => (Method::GET, false),
+        301 | 302 if method == Method::POST => (Method::GET, false),
+        _ => (method.clone(), true),
+    }
+}
+
+fn same_origin(a: &Url, b: &Url) -> bool {
+    a.scheme() == b.scheme() && a.host_str() == b.host_str() && a.port_or_known_default() == b.port_or_known_default()
+}
+
+/// Removes headers that must not carry over to the next hop.
+pub(crate) fn strip_headers(headers: &mut HeaderMap, previous: &Url, next: &Url, keep_body: bool) {
+    if !keep_body {
+        for name in [CONTENT_TYPE, CONTENT_LENGTH, CONTENT_ENCODING, TRANSFER_ENCODING] {
+            headers.remove(name);
+        }
+    }
+    if !same_origin(previous, next) {
+        for name in [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, HOST] {
+            headers.remove(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    #[test]
+    fn rewrites_method_per_status() {
+        assert_eq!(next_method(StatusCode::SEE_OTHER, &Method::PUT), (Method::GET, false));
+        assert_eq!(next_method(StatusCode::SEE_OTHER, &Method::HEAD), (Method::HEAD, true));
+        assert_eq!(next_method(StatusCode::FOUND, &Method::POST), (Method::GET, false));
+        assert_eq!(next_method(StatusCode::MOVED_PERMANENTLY, &Method::PUT), (Method::PUT, true));
+        assert_eq!(next_method(StatusCode::TEMPORARY_REDIRECT, &Method::POST), (Method::POST, true));
+        assert_eq!(next_method(StatusCode::PERMANENT_REDIRECT, &Method::POST), (Method::POST, true));
+    }
+
+    #[test]
+    fn strips_credentials_across_origins_only() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer x"));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        let a = Url::parse("https://example.com/a").unwrap();
+
+        strip_headers(&mut headers, &a, &Url::parse("https://example.com:443/b").unwrap(), true);
+        assert!(headers.contains_key(AUTHORIZATION));
+        assert!(headers.contains_key(CONTENT_TYPE));
+
+        strip_headers(&mut headers, &a, &Url::parse("http://example.com/b").unwrap(), false);
+        assert!(!headers.contains_key(AUTHORIZATION));
+        assert!(!headers.contains_key(CONTENT_TYPE));
+    }
+}
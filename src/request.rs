@@ -0,0 +1,236 @@
+use crate::client::Client;
+use crate::multipart::Form;
+use crate::{Body, Error, Request, Response, Timeouts};
+use http::header::{AUTHORIZATION, CONTENT_TYPE, EXPECT};
+use http::{HeaderMap, HeaderValue, Method, Version};
+use std::fmt::Display;
+use std::time::Duration;
+use url::Url;
+
+/// Builds a `Request` one call at a time; created by `Client::get` and friends.
+///
+/// The first invalid input is kept and returned from `build` or `send`, so
+/// calls can be chained without checking each one.
+#[must_use = "a request does nothing until it is sent"]
+pub struct RequestBuilder {
+    client: Client,
+    request: Result<Request, Error>,
+}
+
+impl RequestBuilder {
+    pub(crate) fn new(client: Client, method: Method, url: &str) -> Self {
+        let request = Url::parse(url)
+            .map_err(|err| Error::InvalidUrl(format!("{}: {}", url, err)))
+            .and_then(|uri| {
+                if !matches!(uri.scheme(), "http" | "https") {
+                    return Err(Error::InvalidUrl(format!("unsupported scheme {}", uri.scheme())));
+                }
+                Ok(Request {
+                    method,
+                    uri,
+                    ..Request::default()
+                })
+            });
+        RequestBuilder { client, request }
+    }
+
+    fn with(mut self, update: impl FnOnce(&mut Request) -> Result<(), Error>) -> Self {
+        if let Ok(request) = &mut self.request {
+            if let Err(err) = update(request) {
+                self.request = Err(err);
+            }
+        }
+        self
+    }
+
+    /// Appends a header, keeping the exact spelling of `name` on the wire.
+    pub fn header<V: TryInto<HeaderValue>>(self, name: &str, value: V) -> Self {
+        self.with(|request| {
+            let value = value.try_into().map_err(|_| Error::InvalidHeader(name.to_string()))?;
+            request.append_header(name, value)
+        })
+    }
+
+    /// Appends every header in `headers`.
+    pub fn headers(self, headers: HeaderMap) -> Self {
+        self.with(|request| {
+            for (name, value) in &headers {
+                request.headers.append(name, value.clone());
+            }
+            Ok(())
+        })
+    }
+
+    /// Appends form-urlencoded pairs to the query string.
+    pub fn query<K: AsRef<str>, V: AsRef<str>>(self, pairs: &[(K, V)]) -> Self {
+        self.with(|request| {
+            if !pairs.is_empty() {
+                request.uri.query_pairs_mut().extend_pairs(pairs);
+            }
+            Ok(())
+        })
+    }
+
+    /// Sets `Authorization: Bearer <token>`, marked sensitive.
+    pub fn bearer_auth<T: Display>(self, token: T) -> Self {
+        self.with(|request| {
+            let mut value = HeaderValue::try_from(format!("Bearer {}", token))
+                .map_err(|_| Error::InvalidHeader("Authorization".to_string()))?;
+            value.set_sensitive(true);
+            request.headers.remove(AUTHORIZATION);
+            request.append_header("Authorization", value)
+        })
+    }
+
+    pub fn body<B: Into<Body>>(self, body: B) -> Self {
+        self.with(|request| {
+            request.body = body.into();
+            Ok(())
+        })
+    }
+
+    /// Serializes `value` as the body and sets `Content-Type: application/json`
+    /// unless a content type was already given.
+    #[cfg(feature = "json")]
+    pub fn json<T: serde::Serialize + ?Sized>(self, value: &T) -> Self {
+        self.with(|request| {
+            request.body = serde_json::to_vec(value).map_err(Error::JsonEncode)?.into();
+            if !request.headers.contains_key(CONTENT_TYPE) {
+                request.append_header("Content-Type", HeaderValue::from_static("application/json"))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Sends `pairs` as an `application/x-www-form-urlencoded` body.
+    pub fn form<K: AsRef<str>, V: AsRef<str>>(self, pairs: &[(K, V)]) -> Self {
+        self.with(|request| {
+            let encoded = url::form_urlencoded::Serializer::new(String::new()).extend_pairs(pairs).finish();
+            request.body = encoded.into();
+            request.headers.remove(CONTENT_TYPE);
+            request.append_header("Content-Type", HeaderValue::from_static("application/x-www-form-urlencoded"))
+        })
+    }
+
+    /// Streams `form` as a `multipart/form-data` body.
+    pub fn multipart(self, form: Form) -> Self {
+        self.with(|request| {
+            request.headers.remove(CONTENT_TYPE);
+            request.append_header("Content-Type", form.content_type())?;
+            request.body = form.into_body();
+            Ok(())
+        })
+    }
+
+    /// Sends `Expect: 100-continue`, so that over HTTP/1 the body is held
+    /// back until the server agrees to take it. A final response that arrives
+    /// first, such as 401 or 413, is returned without sending the body at
+    /// all. HTTP/2 servers reject unwanted uploads by resetting the stream, so
+    /// there the body is sent straight away.
+    pub fn expect_continue(self) -> Self {
+        self.with(|request| {
+            request.headers.remove(EXPECT);
+            request.append_header("Expect", HeaderValue::from_static("100-continue"))
+        })
+    }
+
+    pub fn version(self, version: Version) -> Self {
+        self.with(|request| {
+            request.version = version;
+            Ok(())
+        })
+    }
+
+    /// Overrides the client's default timeouts for this request.
+    pub fn timeouts(self, timeouts: Timeouts) -> Self {
+        self.with(|request| {
+            request.timeouts = timeouts;
+            Ok(())
+        })
+    }
+
+    /// Limits the whole request, including reading the body.
+    pub fn timeout(self, total: Duration) -> Self {
+        self.with(|request| {
+            request.timeouts.total = Some(total);
+            Ok(())
+        })
+    }
+
+    pub fn build(self) -> Result<Request, Error> {
+        self.request
+    }
+
+    pub async fn send(self) -> Result<Response, Error> {
+        self.client.send_request(self.request?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_request_from_chained_calls() {
+        let request = Client::new()
+            .post("http://example.com/search?lang=en")
+            .query(&[("q", "a b&c")])
+            .header("X-Trace-ID", "42")
+            .bearer_auth("secret")
+            .body("payload")
+            .build()
+            .unwrap();
+        assert_eq!(request.method, Method::POST);
+        assert_eq!(request.uri.as_str(), "http://example.com/search?lang=en&q=a+b%26c");
+        assert_eq!(request.headers["x-trace-id"], "42");
+        assert_eq!(request.headers[AUTHORIZATION], "Bearer secret");
+        assert!(request.headers[AUTHORIZATION].is_sensitive());
+        assert_eq!(request.body.as_bytes(), Some(&b"payload"[..]));
+        assert_eq!(request.version, Version::HTTP_11);
+    }
+
+    #[test]
+    fn keeps_first_invalid_input() {
+        let client = Client::new();
+        assert!(matches!(client.get("not a url").build(), Err(Error::InvalidUrl(_))));
+        assert!(matches!(client.get("ftp://example.com/").build(), Err(Error::InvalidUrl(_))));
+        let err = client.get("http://example.com/").header("Bad Name", "v").header("X-Ok", "bad\nvalue").build();
+        assert!(matches!(err, Err(Error::InvalidHeader(name)) if name == "Bad Name"));
+    }
+
+    #[test]
+    fn form_bodies_set_content_type() {
+        let request = Client::new().post("http://example.com/").form(&[("a", "1 2"), ("b", "&=")]).build().unwrap();
+        assert_eq!(request.headers[CONTENT_TYPE], "application/x-www-form-urlencoded");
+        assert_eq!(request.body.as_bytes(), Some(&b"a=1+2&b=%26%3D"[..]));
+
+        let form = Form::new().text("a", "1");
+        let content_type = form.content_type();
+        let request = Client::new().post("http://example.com/").multipart(form).build().unwrap();
+        assert_eq!(request.headers[CONTENT_TYPE], content_type);
+        assert!(!request.body.is_chunked());
+
+        let form = Form::new().part("f", crate::multipart::Part::reader(&b"x"[..]));
+        let request = Client::new().post("http://example.com/").multipart(form).build().unwrap();
+        assert!(request.body.is_chunked());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_body_sets_content_type_unless_given() {
+        let value = serde_json::json!({ "name": "kusari", "tags": ["a", "b"] });
+        let request = Client::new().post("http://example.com/").json(&value).build().unwrap();
+        assert_eq!(request.headers[CONTENT_TYPE], "application/json");
+        let sent: serde_json::Value = serde_json::from_slice(request.body.as_bytes().unwrap()).unwrap();
+        assert_eq!(sent, value);
+
+        let request = Client::new()
+            .post("http://example.com/")
+            .header("Content-Type", "application/merge-patch+json")
+            .json(&value)
+            .build()
+            .unwrap();
+        assert_eq!(request.headers.get_all(CONTENT_TYPE).iter().count(), 1);
+        assert_eq!(request.headers[CONTENT_TYPE], "application/merge-patch+json");
+    }
+}
@@ -0,0 +1,216 @@
+use crate::Error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+/// Time limits for the phases of a request. `None` means no limit.
+///
+/// The client holds defaults; a request's own `Timeouts` override them field
+/// by field.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Establishing the TCP connection, including DNS resolution.
+    pub connect: Option<Duration>,
+    pub tls_handshake: Option<Duration>,
+    /// From the end of the request until the first byte of the response.
+    pub first_byte: Option<Duration>,
+    /// Longest pause allowed while reading or writing once the exchange is underway.
+    pub read_idle: Option<Duration>,
+    /// The whole request, from checkout of a connection to the end of the body.
+    pub total: Option<Duration>,
+    /// How long an HTTP/1 request sent with `Expect: 100-continue` waits for
+    /// the server before sending its body anyway. Unset means one second.
+    pub expect_continue: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Fills every unset field from `defaults`.
+    pub fn or(self, defaults: &Timeouts) -> Timeouts {
+        Timeouts {
+            connect: self.connect.or(defaults.connect),
+            tls_handshake: self.tls_handshake.or(defaults.tls_handshake),
+            first_byte: self.first_byte.or(defaults.first_byte),
+            read_idle: self.read_idle.or(defaults.read_idle),
+            total: self.total.or(defaults.total),
+            expect_continue: self.expect_continue.or(defaults.expect_continue),
+        }
+    }
+}
+
+/// Which limit from `Timeouts` ran out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeoutKind {
+    Connect,
+    TlsHandshake,
+    FirstByte,
+    ReadIdle,
+    Total,
+}
+
+impl fmt::Display for TimeoutKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TimeoutKind::Connect => "connect",
+            TimeoutKind::TlsHandshake => "TLS handshake",
+            TimeoutKind::FirstByte => "first byte",
+            TimeoutKind::ReadIdle => "read idle",
+            TimeoutKind::Total => "total",
+        })
+    }
+}
+
+impl std::error::Error for TimeoutKind {}
+
+pub(crate) async fn timeout<T, F>(limit: Option<Duration>, kind: TimeoutKind, future: F) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, future).await.map_err(|_| Error::Timeout(kind))?,
+        None => future.await,
+    }
+}
+
+/// Wraps a connection so that a read or write making no progress for too
+/// long fails with a `TimedOut` I/O error carrying the `TimeoutKind`.
+///
+/// Until the first response byte is read the `first_byte` limit applies to
+/// reads; after that every operation gets the `read_idle` limit. Reads and
+/// writes are timed separately, so a read abandoned while still pending, such
+/// as the wait for `100 Continue`, does not shorten the writes after it; a
+/// completed write restarts the read timer too. The overall
+/// `deadline` travels with the connection so it also covers a response body
+/// streamed after `send_request` has returned.
+pub(crate) struct Timed<S> {
+    inner: S,
+    first_byte: Option<Duration>,
+    read_idle: Option<Duration>,
+    awaiting_first_byte: bool,
+    read_timer: Option<Pin<Box<Sleep>>>,
+    write_timer: Option<Pin<Box<Sleep>>>,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> Timed<S> {
+    pub fn new(inner: S, timeouts: &Timeouts, deadline: Option<Instant>) -> Self {
+        Timed {
+            inner,
+            first_byte: timeouts.first_byte,
+            read_idle: timeouts.read_idle,
+            awaiting_first_byte: true,
+            read_timer: None,
+            write_timer: None,
+            deadline: deadline.map(|deadline| Box::pin(tokio::time::sleep_until(deadline))),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn poll_timer(&mut self, cx: &mut Context<'_>, reading: bool, limit: Option<Duration>, kind: TimeoutKind) -> Poll<io::Error> {
+        if let Some(deadline) = &mut self.deadline {
+            if deadline.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(io::Error::new(io::ErrorKind::TimedOut, TimeoutKind::Total));
+            }
+        }
+        let Some(limit) = limit else { return Poll::Pending };
+        let slot = if reading { &mut self.read_timer } else { &mut self.write_timer };
+        let timer = slot.get_or_insert_with(|| Box::pin(tokio::time::sleep(limit)));
+        match timer.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                *slot = None;
+                Poll::Ready(io::Error::new(io::ErrorKind::TimedOut, kind))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_io<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        reading: bool,
+        op: impl FnOnce(Pin<&mut S>, &mut Context<'_>) -> Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>>
+    where
+        S: Unpin,
+    {
+        match op(Pin::new(&mut self.inner), cx) {
+            Poll::Ready(result) => {
+                self.read_timer = None;
+                if reading {
+                    self.awaiting_first_byte = false;
+                } else {
+                    self.write_timer = None;
+                }
+                Poll::Ready(result)
+            }
+            Poll::Pending => {
+                let (limit, kind) = if reading && self.awaiting_first_byte {
+                    (self.first_byte, TimeoutKind::FirstByte)
+                } else {
+                    (self.read_idle, TimeoutKind::ReadIdle)
+                };
+                self.poll_timer(cx, reading, limit, kind).map(Err)
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Timed<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_io(cx, true, |inner, cx| inner.poll_read(cx, buf))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Timed<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_io(cx, false, |inner, cx| inner.poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_io(cx, false, |inner, cx| inner.poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_io(cx, false, |inner, cx| inner.poll_shutdown(cx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// A body written after an unanswered wait for `100 Continue` gets its
+    /// own `read_idle` limit, not what is left of the abandoned read's.
+    #[tokio::test]
+    async fn abandoned_reads_do_not_time_later_writes() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(&[0; 64]).await.unwrap();
+        let timeouts = Timeouts {
+            first_byte: Some(Duration::from_millis(100)),
+            read_idle: Some(Duration::from_secs(5)),
+            ..Timeouts::default()
+        };
+        let mut timed = Timed::new(client, &timeouts, None);
+        let continue_wait = tokio::time::timeout(Duration::from_millis(20), timed.read_u8()).await;
+        assert!(continue_wait.is_err());
+
+        let reader = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            server.read_exact(&mut [0; 65]).await.unwrap();
+        });
+        timed.write_all(b"x").await.unwrap();
+        reader.await.unwrap();
+    }
+}
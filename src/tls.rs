@@ -0,0 +1,500 @@
+//! Certificates, client identities, protocol limits, server verification,
+//! session resumption and key logging for `ClientBuilder`.
+
+use crate::Error;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified};
+use rustls::client::{Resumption, WebPkiServerVerifier};
+use rustls::crypto::hash::{Hash, HashAlgorithm};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::{CertificateError, ClientConfig, DigitallySignedStruct, OtherError, RootCertStore, SignatureScheme, SupportedProtocolVersion};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub use rustls::client::danger::ServerCertVerifier;
+pub use rustls::{KeyLog, KeyLogFile};
+
+/// A trusted root certificate.
+#[derive(Clone, Debug)]
+pub struct Certificate(CertificateDer<'static>);
+
+/// A client certificate chain and its private key, presented to servers
+/// that ask for one (mutual TLS).
+#[derive(Debug)]
+pub struct Identity {
+    chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+impl Certificate {
+    pub fn as_der(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+
+    pub fn from_der(der: impl Into<Vec<u8>>) -> Certificate {
+        Certificate(CertificateDer::from(der.into()))
+    }
+
+    /// Every certificate in a PEM bundle; other PEM sections are skipped.
+    pub fn from_pem_bundle(pem: &[u8]) -> Result<Vec<Certificate>, Error> {
+        let certs = CertificateDer::pem_slice_iter(pem)
+            .map(|cert| cert.map(Certificate))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| Error::TlsConfig(format!("invalid PEM certificate: {}", err)))?;
+        if certs.is_empty() {
+            return Err(Error::TlsConfig("no certificate found in PEM data".to_string()));
+        }
+        Ok(certs)
+    }
+
+    /// The first certificate in a PEM file.
+    pub fn from_pem(pem: &[u8]) -> Result<Certificate, Error> {
+        Ok(Certificate::from_pem_bundle(pem)?.remove(0))
+    }
+
+    /// The SHA-256 hash of this certificate's SubjectPublicKeyInfo, the value
+    /// `ClientBuilder::pin_public_key` expects.
+    /// Hashed with the process-default `CryptoProvider`, as clients use.
+    pub fn spki_sha256(&self) -> Result<[u8; 32], Error> {
+        let sha256 = sha256(&default_provider())?;
+        spki_sha256(&self.0, sha256).map_err(|err| Error::TlsConfig(format!("invalid certificate: {}", err)))
+    }
+}
+
+impl Identity {
+    /// A PEM certificate chain, leaf first, and a PEM private key in PKCS#8,
+    /// PKCS#1 or SEC1 form. Both may come from the same file.
+    pub fn from_pem(chain: &[u8], key: &[u8]) -> Result<Identity, Error> {
+        let chain = Certificate::from_pem_bundle(chain)?.into_iter().map(|cert| cert.0).collect();
+        let key = PrivateKeyDer::from_pem_slice(key).map_err(|err| Error::TlsConfig(format!("invalid PEM private key: {}", err)))?;
+        Ok(Identity { chain, key })
+    }
+
+    /// A DER certificate chain, leaf first, and a PKCS#8 DER private key.
+    pub fn from_der(chain: Vec<Vec<u8>>, pkcs8_key: Vec<u8>) -> Identity {
+        Identity {
+            chain: chain.into_iter().map(CertificateDer::from).collect(),
+            key: PrivateKeyDer::Pkcs8(pkcs8_key.into()),
+        }
+    }
+}
+
+impl Clone for Identity {
+    fn clone(&self) -> Self {
+        Identity {
+            chain: self.chain.clone(),
+            key: self.key.clone_key(),
+        }
+    }
+}
+
+/// What the TLS handshake of a response's connection negotiated.
+#[derive(Clone, Debug)]
+pub struct TlsInfo {
+    version: TlsVersion,
+    cipher_suite: rustls::CipherSuite,
+    alpn_protocol: Option<Vec<u8>>,
+    peer_certificates: Vec<Certificate>,
+    resumed: bool,
+    early_data: bool,
+}
+
+impl TlsInfo {
+    /// `None` until the handshake is complete.
+    pub(crate) fn of(conn: &rustls::ClientConnection) -> Option<TlsInfo> {
+        let version = match conn.protocol_version()? {
+            rustls::ProtocolVersion::TLSv1_3 => TlsVersion::Tls13,
+            _ => TlsVersion::Tls12,
+        };
+        Some(TlsInfo {
+            version,
+            cipher_suite: conn.negotiated_cipher_suite()?.suite(),
+            alpn_protocol: conn.alpn_protocol().map(<[u8]>::to_vec),
+            peer_certificates: conn
+                .peer_certificates()
+                .unwrap_or_default()
+                .iter()
+                .map(|cert| Certificate(cert.clone().into_owned()))
+                .collect(),
+            resumed: conn.handshake_kind() == Some(rustls::HandshakeKind::Resumed),
+            early_data: conn.is_early_data_accepted(),
+        })
+    }
+
+    pub fn version(&self) -> TlsVersion {
+        self.version
+    }
+
+    pub fn cipher_suite(&self) -> rustls::CipherSuite {
+        self.cipher_suite
+    }
+
+    /// The protocol the server picked through ALPN, such as `b"h2"`.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_deref()
+    }
+
+    /// The chain the server presented, leaf first.
+    pub fn peer_certificates(&self) -> &[Certificate] {
+        &self.peer_certificates
+    }
+
+    /// Whether the handshake resumed an earlier session instead of
+    /// authenticating the server afresh.
+    pub fn resumed(&self) -> bool {
+        self.resumed
+    }
+
+    /// Whether the server accepted the request sent as 0-RTT early data.
+    pub fn early_data(&self) -> bool {
+        self.early_data
+    }
+}
+
+/// Counts of the TLS handshakes a client has completed, from
+/// `Client::tls_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TlsStats {
+    pub handshakes: u64,
+    /// Handshakes that resumed a cached session.
+    pub resumed: u64,
+    /// Handshakes that sent a request as 0-RTT early data.
+    pub early_data_sent: u64,
+    /// Of those, the ones where the server accepted it.
+    pub early_data_accepted: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    handshakes: AtomicU64,
+    resumed: AtomicU64,
+    early_data_sent: AtomicU64,
+    early_data_accepted: AtomicU64,
+}
+
+impl Counters {
+    pub fn record(&self, info: &TlsInfo, early_data_sent: bool) {
+        self.handshakes.fetch_add(1, Ordering::Relaxed);
+        if info.resumed {
+            self.resumed.fetch_add(1, Ordering::Relaxed);
+        }
+        if early_data_sent {
+            self.early_data_sent.fetch_add(1, Ordering::Relaxed);
+        }
+        if info.early_data {
+            self.early_data_accepted.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> TlsStats {
+        TlsStats {
+            handshakes: self.handshakes.load(Ordering::Relaxed),
+            resumed: self.resumed.load(Ordering::Relaxed),
+            early_data_sent: self.early_data_sent.load(Ordering::Relaxed),
+            early_data_accepted: self.early_data_accepted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A `KeyLog` that hands each secret to a closure, e.g. to collect them in
+/// memory in a test.
+pub struct KeyLogFn<F>(F);
+
+impl<F> KeyLogFn<F>
+where
+    F: Fn(&str, &[u8], &[u8]) + Send + Sync,
+{
+    /// `log` receives the NSS key log label, the client random and the secret.
+    pub fn new(log: F) -> Self {
+        KeyLogFn(log)
+    }
+}
+
+impl<F> KeyLog for KeyLogFn<F>
+where
+    F: Fn(&str, &[u8], &[u8]) + Send + Sync,
+{
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        (self.0)(label, client_random, secret)
+    }
+}
+
+impl<F> fmt::Debug for KeyLogFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeyLogFn")
+    }
+}
+
+/// The error a pinning verifier fails the handshake with; the client reports
+/// it as `Error::CertificatePin`.
+#[derive(Debug)]
+pub(crate) struct PinMismatch;
+
+impl fmt::Display for PinMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("server public key does not match any pin")
+    }
+}
+
+impl std::error::Error for PinMismatch {}
+
+/// The installed default provider, or aws-lc-rs when none is installed.
+fn default_provider() -> CryptoProvider {
+    CryptoProvider::get_default()
+        .map(|provider| CryptoProvider::clone(provider))
+        .unwrap_or_else(rustls::crypto::aws_lc_rs::default_provider)
+}
+
+/// `provider`'s SHA-256, borrowed from one of its TLS 1.3 cipher suites.
+fn sha256(provider: &CryptoProvider) -> Result<&'static dyn Hash, Error> {
+    provider
+        .cipher_suites
+        .iter()
+        .filter_map(|suite| suite.tls13())
+        .map(|suite| suite.common.hash_provider)
+        .find(|hash| hash.algorithm() == HashAlgorithm::SHA256)
+        .ok_or_else(|| Error::TlsConfig("crypto provider has no SHA-256".to_string()))
+}
+
+fn spki_sha256(cert: &CertificateDer<'_>, sha256: &dyn Hash) -> Result<[u8; 32], webpki::Error> {
+    let cert = webpki::EndEntityCert::try_from(cert)?;
+    let hash = sha256.hash(cert.subject_public_key_info().as_ref());
+    let mut out = [0; 32];
+    out.copy_from_slice(hash.as_ref());
+    Ok(out)
+}
+
+/// Runs the usual verification, then requires the leaf certificate's key to
+/// be one of the pins for hosts that have any. Intermediates are not
+/// consulted, since a misissued chain can present any of them.
+struct PinningVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    pins: HashMap<String, Vec<[u8; 32]>>,
+    sha256: &'static dyn Hash,
+}
+
+impl fmt::Debug for PinningVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PinningVerifier").field("inner", &self.inner).field("pins", &self.pins).finish_non_exhaustive()
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_ascii_lowercase(),
+            ServerName::IpAddress(ip) => std::net::IpAddr::from(*ip).to_string(),
+            _ => return Ok(verified),
+        };
+        let Some(pins) = self.pins.get(&host) else {
+            return Ok(verified);
+        };
+        let hash = spki_sha256(end_entity, self.sha256).map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        if pins.contains(&hash) {
+            Ok(verified)
+        } else {
+            Err(rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(Arc::new(PinMismatch)))))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Accepts any certificate for any name. Handshake signatures are still
+/// checked, which proves only that the server holds the key it presented.
+#[derive(Debug)]
+struct DangerAcceptInvalidCerts(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for DangerAcceptInvalidCerts {
+    fn verify_server_cert(
+        &self,
+        _: &CertificateDer<'_>,
+        _: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        _: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// How the server's certificate is checked.
+#[derive(Clone, Debug, Default)]
+pub(crate) enum Verification {
+    /// Against the configured roots.
+    #[default]
+    Roots,
+    Custom(Arc<dyn ServerCertVerifier>),
+    DangerAcceptInvalidCerts,
+}
+
+/// Everything `ClientBuilder` collects about TLS before building a config.
+#[derive(Clone, Debug)]
+pub(crate) struct Options {
+    pub built_in_roots: bool,
+    #[cfg(feature = "native-roots")]
+    pub native_roots: bool,
+    pub roots: Vec<Certificate>,
+    pub identity: Option<Identity>,
+    pub min_version: TlsVersion,
+    pub cipher_suites: Option<Vec<rustls::CipherSuite>>,
+    pub key_log: Option<Arc<dyn KeyLog>>,
+    pub verification: Verification,
+    /// SPKI SHA-256 hashes by lowercase host.
+    pub pins: HashMap<String, Vec<[u8; 32]>>,
+    /// How many sessions to keep for resumption; 0 turns resumption off.
+    pub session_cache_size: usize,
+    /// Read by `ClientBuilder::build`, which keeps a second config for 0-RTT.
+    pub early_data: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            built_in_roots: true,
+            #[cfg(feature = "native-roots")]
+            native_roots: false,
+            roots: Vec::new(),
+            identity: None,
+            min_version: TlsVersion::Tls12,
+            cipher_suites: None,
+            key_log: None,
+            verification: Verification::default(),
+            pins: HashMap::new(),
+            session_cache_size: 256,
+            early_data: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn build(self) -> Result<ClientConfig, Error> {
+        let mut roots = RootCertStore::empty();
+        if self.built_in_roots {
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+        #[cfg(feature = "native-roots")]
+        if self.native_roots {
+            let native = rustls_native_certs::load_native_certs();
+            if native.certs.is_empty() {
+                if let Some(err) = native.errors.first() {
+                    return Err(Error::TlsConfig(format!("failed to load native certificates: {}", err)));
+                }
+            }
+            // Stores often hold a few certificates webpki cannot parse.
+            roots.add_parsable_certificates(native.certs);
+        }
+        for root in self.roots {
+            roots.add(root.0).map_err(|err| Error::TlsConfig(format!("invalid root certificate: {}", err)))?;
+        }
+
+        let mut provider = default_provider();
+        // Taken before the suites are narrowed, which may leave no SHA-256 one.
+        let sha256 = sha256(&provider)?;
+        if let Some(suites) = &self.cipher_suites {
+            provider.cipher_suites.retain(|suite| suites.contains(&suite.suite()));
+        }
+        let versions: &[&'static SupportedProtocolVersion] = match self.min_version {
+            TlsVersion::Tls12 => rustls::ALL_VERSIONS,
+            TlsVersion::Tls13 => &[&rustls::version::TLS13],
+        };
+        let provider = Arc::new(provider);
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(versions)
+            .map_err(|err| Error::TlsConfig(err.to_string()))?;
+        let builder = if matches!(self.verification, Verification::Roots) && self.pins.is_empty() {
+            builder.with_root_certificates(roots)
+        } else {
+            let verifier: Arc<dyn ServerCertVerifier> = match self.verification {
+                Verification::Roots => WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                    .build()
+                    .map_err(|err| Error::TlsConfig(err.to_string()))?,
+                Verification::Custom(verifier) => verifier,
+                Verification::DangerAcceptInvalidCerts => Arc::new(DangerAcceptInvalidCerts(provider)),
+            };
+            let verifier = if self.pins.is_empty() {
+                verifier
+            } else {
+                Arc::new(PinningVerifier {
+                    inner: verifier,
+                    pins: self.pins,
+                    sha256,
+                })
+            };
+            builder.dangerous().with_custom_certificate_verifier(verifier)
+        };
+        let mut config = match self.identity {
+            Some(identity) => builder
+                .with_client_auth_cert(identity.chain, identity.key)
+                .map_err(|err| Error::TlsConfig(format!("invalid client certificate: {}", err)))?,
+            None => builder.with_no_client_auth(),
+        };
+        if let Some(key_log) = self.key_log {
+            config.key_log = key_log;
+        }
+        config.resumption = match self.session_cache_size {
+            0 => Resumption::disabled(),
+            size => Resumption::in_memory_sessions(size),
+        };
+        Ok(config)
+    }
+}